@@ -0,0 +1,206 @@
+use std::{
+    fmt,
+    io::{self, BufRead, Read, Write},
+};
+
+/// Frames everything written to it as `Transfer-Encoding: chunked`.
+///
+/// Each `write` becomes one chunk; `finish` writes the terminating chunk and
+/// must be called once the body is complete.
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // an empty chunk would end the body early
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.inner, "{:X}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Longest chunk-size or trailer line accepted, extensions included.
+const MAX_LINE: u64 = 4096;
+
+/// Reads a body sent with `Transfer-Encoding: chunked`, yielding the chunk
+/// data and stopping after the terminating chunk and its trailers, which
+/// are discarded.
+///
+/// Malformed framing is an `InvalidData` error and a connection that closes
+/// mid-body an `UnexpectedEof` error, so a cut-off upload is never taken for
+/// a complete one.
+pub struct ChunkedReader<R: BufRead> {
+    inner: R,
+    /// Data bytes left in the current chunk.
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            remaining: 0,
+            done: false,
+        }
+    }
+
+    /// One CRLF-terminated line, without the CRLF.
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line = Vec::new();
+        (&mut self.inner)
+            .take(MAX_LINE)
+            .read_until(b'\n', &mut line)?;
+        if !line.ends_with(b"\n") {
+            return Err(match line.len() as u64 {
+                MAX_LINE => invalid("chunk line too long"),
+                _ => io::Error::new(io::ErrorKind::UnexpectedEof, "body ended mid-chunk"),
+            });
+        }
+        line.pop();
+        if line.pop() != Some(b'\r') {
+            return Err(invalid("chunk line not ended by CRLF"));
+        }
+        Ok(line)
+    }
+
+    /// Read the next chunk-size line, or the trailers after the last chunk.
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let line = self.read_line()?;
+        // chunk extensions are allowed and ignored
+        let size = line.split(|&b| b == b';').next().unwrap_or_default();
+        let size = std::str::from_utf8(size)
+            .ok()
+            .map(|size| size.trim())
+            .filter(|size| !size.is_empty() && size.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|size| u64::from_str_radix(size, 16).ok())
+            .ok_or_else(|| invalid("malformed chunk size"))?;
+        if size == 0 {
+            while !self.read_line()?.is_empty() {}
+            self.done = true;
+        }
+        self.remaining = size;
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.remaining == 0 && !self.done {
+            self.next_chunk()?;
+        }
+        if self.done {
+            return Ok(0);
+        }
+
+        let max = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "body ended mid-chunk",
+            ));
+        }
+        self.remaining -= n as u64;
+        if self.remaining == 0 && !self.read_line()?.is_empty() {
+            return Err(invalid("chunk data longer than its size"));
+        }
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> fmt::Debug for ChunkedReader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChunkedReader")
+            .field("remaining", &self.remaining)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+fn invalid(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(body: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        ChunkedReader::new(body).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn decodes_what_the_writer_frames() {
+        let mut writer = ChunkedWriter::new(Vec::new());
+        writer.write_all(b"some ").unwrap();
+        writer.write_all(b"data here").unwrap();
+        let framed = writer.finish().unwrap();
+        assert_eq!(decode(&framed).unwrap(), b"some data here");
+    }
+
+    #[test]
+    fn skips_extensions_and_trailers() {
+        let body = b"4;name=value\r\nWiki\r\n0\r\nExpires: never\r\n\r\n";
+        assert_eq!(decode(body).unwrap(), b"Wiki");
+    }
+
+    #[test]
+    fn stops_at_the_last_chunk() {
+        let mut reader = ChunkedReader::new(&b"2\r\nab\r\n0\r\n\r\nGET / HTTP/1.1"[..]);
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"ab");
+        // the next request stays on the connection
+        assert_eq!(reader.inner, b"GET / HTTP/1.1");
+    }
+
+    #[test]
+    fn rejects_malformed_framing() {
+        for body in [
+            &b"x\r\nab\r\n0\r\n\r\n"[..],
+            b"+2\r\nab\r\n0\r\n\r\n",
+            b"2\r\nabc\r\n0\r\n\r\n",
+            b"2\nab\r\n0\r\n\r\n",
+        ] {
+            let err = decode(body).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn truncated_bodies_are_unexpected_eof() {
+        for body in [&b"5\r\nab"[..], b"2\r\nab\r\n", b"2\r\nab\r\n0\r\n"] {
+            let err = decode(body).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+}
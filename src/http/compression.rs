@@ -0,0 +1,142 @@
+use super::{
+    request::RequestBody,
+    response::{Response, StatusCode},
+};
+
+/// Decides which responses are worth compressing.
+///
+/// Content types are matched without parameters; an entry ending in `/*`
+/// matches every subtype. A type on the deny list is never compressed, and
+/// when the allow list is non-empty only types on it are.
+#[derive(Debug, Clone)]
+pub struct CompressionPolicy {
+    min_size: usize,
+    stream_threshold: usize,
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        Self {
+            min_size: 256,
+            stream_threshold: 1024 * 1024,
+            allow: Vec::new(),
+            // formats that are already compressed gain nothing from another pass
+            deny: [
+                "image/*",
+                "video/*",
+                "audio/*",
+                "font/woff",
+                "font/woff2",
+                "application/zip",
+                "application/gzip",
+                "application/x-gzip",
+                "application/zstd",
+                "application/x-7z-compressed",
+                "application/x-bzip2",
+                "application/x-xz",
+            ]
+            .iter()
+            .map(|t| t.to_string())
+            .collect(),
+        }
+    }
+}
+
+impl CompressionPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bodies smaller than `min_size` bytes are sent as they are.
+    pub fn set_min_size(&mut self, min_size: usize) {
+        self.min_size = min_size;
+    }
+
+    /// Buffered bodies larger than `stream_threshold` bytes are compressed
+    /// while they are written, using chunked transfer-encoding, rather than
+    /// all at once up front. Streamed bodies always are.
+    pub fn set_stream_threshold(&mut self, stream_threshold: usize) {
+        self.stream_threshold = stream_threshold;
+    }
+
+    pub fn get_stream_threshold(&self) -> usize {
+        self.stream_threshold
+    }
+
+    pub fn add_allowed_type(&mut self, content_type: &str) {
+        self.allow.push(content_type.to_lowercase());
+    }
+
+    pub fn add_denied_type(&mut self, content_type: &str) {
+        self.deny.push(content_type.to_lowercase());
+    }
+
+    pub fn clear_denied_types(&mut self) {
+        self.deny.clear();
+    }
+
+    /// Whether the policy lets `content_type` be compressed at all.
+    pub fn is_compressible_type(&self, content_type: &str) -> bool {
+        let media_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_lowercase();
+        let matches = |pattern: &String| match pattern.strip_suffix("/*") {
+            Some(main_type) => media_type.split('/').next() == Some(main_type),
+            None => *pattern == media_type,
+        };
+
+        !self.deny.iter().any(matches) && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+
+    /// Whether `res` would be compressed for a client that accepts it. These
+    /// responses vary by `Accept-Encoding` whatever the client sent.
+    pub fn should_compress(&self, res: &Response) -> bool {
+        // a partial body's offsets refer to the bytes as they are stored
+        if res.get_header("Content-Encoding").is_some()
+            || res.get_status_code() == StatusCode::PartialContent
+        {
+            return false;
+        }
+
+        // a streamed body's size is only known if the handler declared it
+        let size = match res.get_body() {
+            Some(RequestBody::String(bytes)) => Some(bytes.len()),
+            None if res.has_stream() => res
+                .get_header("Content-Length")
+                .and_then(|length| length.parse::<usize>().ok()),
+            None => Some(0),
+        };
+        if matches!(size, Some(size) if size == 0 || size < self.min_size) {
+            return false;
+        }
+
+        let content_type = res
+            .get_header("Content-Type")
+            .map(|ct| ct.as_str())
+            .unwrap_or("text/plain");
+        self.is_compressible_type(content_type)
+    }
+}
+
+/// Add `field` to the response's `Vary` header unless it is already covered.
+pub fn add_vary(res: &mut Response, field: &str) {
+    let vary = match res.get_header("Vary") {
+        Some(vary) => {
+            let covered = vary
+                .split(',')
+                .map(|v| v.trim())
+                .any(|v| v == "*" || v.eq_ignore_ascii_case(field));
+            if covered {
+                return;
+            }
+            format!("{}, {}", vary, field)
+        }
+        None => field.to_string(),
+    };
+    res.set_header("Vary", &vary);
+}
@@ -0,0 +1,397 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt, fs,
+    hash::Hasher,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use httpdate::HttpDate;
+
+use super::{
+    into_response::IntoResponse,
+    request::{Method, Request},
+    response::{Response, StatusCode},
+};
+
+/// Headers a `304 Not Modified` repeats from the response it stands in for.
+const NOT_MODIFIED_HEADERS: &[&str] = &[
+    "ETag",
+    "Last-Modified",
+    "Vary",
+    "Cache-Control",
+    "Content-Location",
+    "Expires",
+];
+
+/// An entity tag, as sent in `ETag` and compared against `If-Match`,
+/// `If-None-Match` and `If-Range`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETag {
+    weak: bool,
+    tag: String,
+}
+
+impl ETag {
+    pub fn strong(tag: &str) -> Self {
+        Self {
+            weak: false,
+            tag: tag.to_string(),
+        }
+    }
+
+    pub fn weak(tag: &str) -> Self {
+        Self {
+            weak: true,
+            tag: tag.to_string(),
+        }
+    }
+
+    /// A strong tag for a file, derived from its size and modification time.
+    pub fn from_metadata(metadata: &fs::Metadata) -> Self {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        Self::strong(&format!("{:x}-{:x}", metadata.len(), modified))
+    }
+
+    /// A strong tag for a body, derived from a hash of its bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(bytes);
+        Self::strong(&format!("{:x}-{:016x}", bytes.len(), hasher.finish()))
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// Both tags are strong and identical; what `If-Match` and `If-Range`
+    /// require.
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// The tags are identical, ignoring weakness; what `If-None-Match`
+    /// requires.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+impl FromStr for ETag {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (weak, quoted) = match s.strip_prefix("W/") {
+            Some(quoted) => (true, quoted),
+            None => (false, s),
+        };
+        let tag = quoted
+            .strip_prefix('"')
+            .and_then(|q| q.strip_suffix('"'))
+            .filter(|tag| !tag.contains('"'))
+            .ok_or(())?;
+        Ok(Self {
+            weak,
+            tag: tag.to_string(),
+        })
+    }
+}
+
+/// The value of `If-Match` or `If-None-Match`.
+enum TagList {
+    Any,
+    Tags(Vec<ETag>),
+}
+
+impl TagList {
+    /// Tags are quoted and may themselves contain commas, so the list can't
+    /// simply be split. Malformed entries end the list.
+    fn parse(header: &str) -> Self {
+        if header.trim() == "*" {
+            return TagList::Any;
+        }
+
+        let mut tags = Vec::new();
+        let mut rest = header;
+        loop {
+            rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+            let start = if rest.starts_with("W/\"") { 3 } else { 1 };
+            if !rest[start - 1..].starts_with('"') {
+                break;
+            }
+            let end = match rest[start..].find('"') {
+                Some(end) => start + end + 1,
+                None => break,
+            };
+            tags.extend(rest[..end].parse::<ETag>());
+            rest = &rest[end..];
+        }
+        TagList::Tags(tags)
+    }
+
+    fn matches(&self, current: Option<&ETag>, eq: fn(&ETag, &ETag) -> bool) -> bool {
+        match (self, current) {
+            (TagList::Any, current) => current.is_some(),
+            (TagList::Tags(tags), Some(current)) => tags.iter().any(|tag| eq(tag, current)),
+            (TagList::Tags(_), None) => false,
+        }
+    }
+}
+
+/// The validators of a resource's current representation.
+#[derive(Debug, Clone, Default)]
+pub struct Validators {
+    pub etag: Option<ETag>,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// The validators a response carries in its `ETag` and `Last-Modified`
+    /// headers.
+    pub fn from_response(res: &Response) -> Self {
+        Self {
+            etag: res.get_header("ETag").and_then(|etag| etag.parse().ok()),
+            last_modified: res
+                .get_header("Last-Modified")
+                .and_then(|date| date.parse::<HttpDate>().ok())
+                .map(SystemTime::from),
+        }
+    }
+
+    pub fn from_metadata(metadata: &fs::Metadata) -> Self {
+        Self {
+            etag: Some(ETag::from_metadata(metadata)),
+            last_modified: metadata.modified().ok(),
+        }
+    }
+}
+
+/// Evaluate the preconditions of `req` in the order RFC 9110 §13.2.2 gives
+/// them, against `current`, or `None` when the target has no current
+/// representation.
+///
+/// Returns the status to answer with instead of performing the request:
+/// `304 Not Modified` for a `GET` whose cached copy is still fresh, or
+/// `412 Precondition Failed`. `If-Range` is left to the range handling.
+///
+/// The server checks `GET` responses itself; handlers of unsafe methods
+/// call this before making any change.
+pub fn evaluate(req: &Request, current: Option<&Validators>) -> Option<StatusCode> {
+    let headers = req.get_headers();
+    let etag = current.and_then(|c| c.etag.as_ref());
+    let last_modified = current.and_then(|c| c.last_modified).map(HttpDate::from);
+    let date = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|date| date.trim().parse::<HttpDate>().ok())
+    };
+
+    if let Some(if_match) = headers.get("if-match") {
+        if !TagList::parse(if_match).matches(etag, ETag::strong_eq) {
+            return Some(StatusCode::PreconditionFailed);
+        }
+    } else if let (Some(since), Some(modified)) = (date("if-unmodified-since"), last_modified) {
+        if modified > since {
+            return Some(StatusCode::PreconditionFailed);
+        }
+    }
+
+    let safe = req.get_method() == Method::GET;
+    if let Some(if_none_match) = headers.get("if-none-match") {
+        if TagList::parse(if_none_match).matches(etag, ETag::weak_eq) {
+            return Some(match safe {
+                true => StatusCode::NotModified,
+                false => StatusCode::PreconditionFailed,
+            });
+        }
+    } else if let (true, Some(since), Some(modified)) =
+        (safe, date("if-modified-since"), last_modified)
+    {
+        if modified <= since {
+            return Some(StatusCode::NotModified);
+        }
+    }
+
+    None
+}
+
+/// A `304 Not Modified` standing in for `res`, keeping only the headers a
+/// cache needs to update its stored copy, along with its cookies.
+pub fn not_modified(res: &Response) -> Response {
+    let headers = NOT_MODIFIED_HEADERS
+        .iter()
+        .filter_map(|name| {
+            res.get_header(name)
+                .map(|value| (name.to_string(), value.clone()))
+        })
+        .collect::<HashMap<_, _>>();
+    let mut not_modified = StatusCode::NotModified.into_response();
+    not_modified.set_headers(headers);
+    for cookie in res.get_cookies() {
+        not_modified.add_cookie(cookie.clone());
+    }
+    not_modified
+}
+
+/// Mark a strong `ETag` on `res` as weak, because the body is about to be
+/// changed in a way that keeps its meaning but not its bytes.
+pub fn weaken_etag(res: &mut Response) {
+    let weakened = res
+        .get_header("ETag")
+        .and_then(|etag| etag.parse::<ETag>().ok())
+        .filter(|etag| !etag.is_weak())
+        .map(|etag| ETag::weak(&etag.tag));
+    if let Some(etag) = weakened {
+        res.set_header("ETag", &etag.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn request(method: &str, headers: &[(&str, &str)]) -> Request {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}\r\n", name, value))
+            .collect::<String>();
+        format!(
+            "{} /file HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+            method, headers
+        )
+        .parse()
+        .unwrap()
+    }
+
+    fn tags(header: &str) -> Vec<ETag> {
+        match TagList::parse(header) {
+            TagList::Any => panic!("`{}` parsed as `*`", header),
+            TagList::Tags(tags) => tags,
+        }
+    }
+
+    fn validators(etag: &str) -> Validators {
+        Validators {
+            etag: Some(etag.parse().unwrap()),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(1_000_000_000)),
+        }
+    }
+
+    #[test]
+    fn parses_tag_lists() {
+        assert!(matches!(TagList::parse(" * "), TagList::Any));
+        assert_eq!(
+            tags(r#""a", W/"b",,"c,d""#),
+            vec![ETag::strong("a"), ETag::weak("b"), ETag::strong("c,d")]
+        );
+        // a malformed entry ends the list
+        assert_eq!(tags(r#""a", b, "c""#), vec![ETag::strong("a")]);
+        assert_eq!(tags(r#""a", "unterminated"#), vec![ETag::strong("a")]);
+        assert!(tags("").is_empty());
+    }
+
+    #[test]
+    fn if_match_compares_strongly() {
+        let current = validators(r#""v1""#);
+        let req = request("GET", &[("If-Match", r#""v1""#)]);
+        assert_eq!(evaluate(&req, Some(&current)), None);
+        let req = request("GET", &[("If-Match", r#"W/"v1""#)]);
+        assert_eq!(
+            evaluate(&req, Some(&current)),
+            Some(StatusCode::PreconditionFailed)
+        );
+        let current = validators(r#"W/"v1""#);
+        let req = request("GET", &[("If-Match", r#"W/"v1""#)]);
+        assert_eq!(
+            evaluate(&req, Some(&current)),
+            Some(StatusCode::PreconditionFailed)
+        );
+    }
+
+    #[test]
+    fn if_match_any_needs_a_current_representation() {
+        let req = request("PUT", &[("If-Match", "*")]);
+        assert_eq!(evaluate(&req, Some(&validators(r#""v1""#))), None);
+        assert_eq!(evaluate(&req, None), Some(StatusCode::PreconditionFailed));
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        let current = validators(r#""v1""#);
+        let req = request("GET", &[("If-None-Match", r#""v0", W/"v1""#)]);
+        assert_eq!(
+            evaluate(&req, Some(&current)),
+            Some(StatusCode::NotModified)
+        );
+        let req = request("GET", &[("If-None-Match", r#""v0""#)]);
+        assert_eq!(evaluate(&req, Some(&current)), None);
+    }
+
+    #[test]
+    fn if_none_match_overrides_if_modified_since() {
+        let current = validators(r#""v1""#);
+        let later = "Sun, 01 Jan 2040 00:00:00 GMT";
+        let req = request("GET", &[("If-Modified-Since", later)]);
+        assert_eq!(
+            evaluate(&req, Some(&current)),
+            Some(StatusCode::NotModified)
+        );
+        // the date would say not modified, but the tag has changed
+        let req = request(
+            "GET",
+            &[("If-None-Match", r#""v0""#), ("If-Modified-Since", later)],
+        );
+        assert_eq!(evaluate(&req, Some(&current)), None);
+    }
+
+    #[test]
+    fn unsafe_methods_fail_instead_of_not_modified() {
+        let current = validators(r#""v1""#);
+        let req = request("PUT", &[("If-None-Match", "*")]);
+        assert_eq!(
+            evaluate(&req, Some(&current)),
+            Some(StatusCode::PreconditionFailed)
+        );
+        // creating a file only when there isn't one yet
+        assert_eq!(evaluate(&req, None), None);
+        let req = request("PUT", &[("If-Match", r#""v0""#)]);
+        assert_eq!(
+            evaluate(&req, Some(&current)),
+            Some(StatusCode::PreconditionFailed)
+        );
+    }
+
+    #[test]
+    fn if_unmodified_since_fails_after_a_change() {
+        let current = validators(r#""v1""#);
+        let earlier = "Thu, 01 Jan 1998 00:00:00 GMT";
+        let req = request("PUT", &[("If-Unmodified-Since", earlier)]);
+        assert_eq!(
+            evaluate(&req, Some(&current)),
+            Some(StatusCode::PreconditionFailed)
+        );
+        // If-Match takes precedence
+        let req = request(
+            "PUT",
+            &[("If-Match", r#""v1""#), ("If-Unmodified-Since", earlier)],
+        );
+        assert_eq!(evaluate(&req, Some(&current)), None);
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use httpdate::HttpDate;
+
+/// 9999-12-31T23:59:59Z, the last moment an HTTP date can express.
+const MAX_EXPIRES: Duration = Duration::from_secs(253_402_300_799);
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SameSite::Strict => write!(f, "Strict"),
+            SameSite::Lax => write!(f, "Lax"),
+            SameSite::None => write!(f, "None"),
+        }
+    }
+}
+
+/// A cookie to send with `Set-Cookie`, added with `Response::add_cookie`.
+///
+/// The name and value are sent as they are, so they should already be
+/// restricted to cookie-safe characters; control characters and `;` are
+/// dropped rather than allowed to break the header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie that tells the client to delete `name`. Its `Path` and
+    /// `Domain` must match the ones the cookie was set with.
+    pub fn removal(name: &str) -> Self {
+        let mut cookie = Self::new(name, "");
+        cookie.set_max_age(Duration::ZERO);
+        cookie.set_expires(SystemTime::UNIX_EPOCH);
+        cookie
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn set_value(&mut self, value: &str) {
+        self.value = value.to_string();
+    }
+
+    pub fn get_path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn set_path(&mut self, path: &str) {
+        self.path = Some(path.to_string());
+    }
+
+    pub fn get_domain(&self) -> Option<&str> {
+        self.domain.as_deref()
+    }
+
+    pub fn set_domain(&mut self, domain: &str) {
+        self.domain = Some(domain.to_string());
+    }
+
+    /// Lifetime from now, sent as `Max-Age` in whole seconds.
+    pub fn set_max_age(&mut self, max_age: Duration) {
+        self.max_age = Some(max_age);
+    }
+
+    /// Sent as `Expires`. Times outside 1970..=9999 have no HTTP date, so
+    /// they are clamped to that range.
+    pub fn set_expires(&mut self, expires: SystemTime) {
+        let since_epoch = expires
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        self.expires = Some(SystemTime::UNIX_EPOCH + since_epoch.min(MAX_EXPIRES));
+    }
+
+    pub fn set_secure(&mut self, secure: bool) {
+        self.secure = secure;
+    }
+
+    pub fn set_http_only(&mut self, http_only: bool) {
+        self.http_only = http_only;
+    }
+
+    /// `SameSite::None` requires `Secure`, so it sets it too.
+    pub fn set_same_site(&mut self, same_site: SameSite) {
+        if same_site == SameSite::None {
+            self.secure = true;
+        }
+        self.same_site = Some(same_site);
+    }
+}
+
+/// Formats the cookie as the value of a `Set-Cookie` header.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", sanitize(&self.name), sanitize(&self.value))?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", sanitize(path))?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", sanitize(domain))?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", HttpDate::from(expires))?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+fn sanitize(s: &str) -> String {
+    s.chars().filter(|c| !c.is_control() && *c != ';').collect()
+}
+
+/// The cookies a request carries in its `Cookie` header.
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: HashMap<String, String>,
+}
+
+impl CookieJar {
+    /// Parse a `Cookie` header. Pairs without `=` are skipped, and when a
+    /// name repeats the first value wins, as it is the most specific.
+    pub fn parse(header: &str) -> Self {
+        let mut cookies = HashMap::new();
+        for pair in header.split(';') {
+            if let Some((name, value)) = pair.split_once('=') {
+                let name = name.trim();
+                // values may be sent quoted
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                if !name.is_empty() {
+                    cookies
+                        .entry(name.to_string())
+                        .or_insert_with(|| value.to_string());
+                }
+            }
+        }
+        Self { cookies }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies.get(name).map(|value| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.cookies.contains_key(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.cookies
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_set_cookie() {
+        let mut cookie = Cookie::new("id", "a;b\nc");
+        cookie.set_path("/");
+        cookie.set_max_age(Duration::from_secs(60));
+        cookie.set_http_only(true);
+        cookie.set_same_site(SameSite::None);
+        assert_eq!(
+            cookie.to_string(),
+            "id=abc; Path=/; Max-Age=60; Secure; HttpOnly; SameSite=None"
+        );
+        assert_eq!(
+            Cookie::removal("id").to_string(),
+            "id=; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn clamps_expires_to_http_dates() {
+        let mut cookie = Cookie::new("id", "1");
+        cookie.set_expires(SystemTime::UNIX_EPOCH + Duration::from_secs(400_000_000_000));
+        assert_eq!(
+            cookie.to_string(),
+            "id=1; Expires=Fri, 31 Dec 9999 23:59:59 GMT"
+        );
+        cookie.set_expires(SystemTime::UNIX_EPOCH - Duration::from_secs(1));
+        assert_eq!(
+            cookie.to_string(),
+            "id=1; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn parses_cookie_headers() {
+        let jar = CookieJar::parse(r#"a=1; b="quoted value"; c=; d="unbalanced"#);
+        assert_eq!(jar.get("a"), Some("1"));
+        assert_eq!(jar.get("b"), Some("quoted value"));
+        assert_eq!(jar.get("c"), Some(""));
+        assert_eq!(jar.get("d"), Some("\"unbalanced"));
+        assert_eq!(jar.len(), 4);
+    }
+
+    #[test]
+    fn first_duplicate_wins() {
+        let jar = CookieJar::parse("id=specific; other=x; id=general");
+        assert_eq!(jar.get("id"), Some("specific"));
+        assert_eq!(jar.len(), 2);
+    }
+
+    #[test]
+    fn skips_pairs_without_a_name_or_value() {
+        let jar = CookieJar::parse("flag; =orphan; ;  ok = yes ;");
+        assert_eq!(jar.iter().collect::<Vec<_>>(), vec![("ok", "yes")]);
+        assert!(!jar.contains("flag"));
+        assert!(CookieJar::parse("").is_empty());
+    }
+}
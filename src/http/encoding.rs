@@ -1,62 +1,523 @@
-use itertools::Itertools;
+use std::{
+    collections::HashMap,
+    error, fmt,
+    io::{self, Read, Write},
+};
 
-#[derive(Debug, Clone)]
+use flate2::{
+    read::{self as flate_read, MultiGzDecoder, ZlibDecoder},
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+
+/// A content coding the server can apply to a response body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum EncodingType {
-    Gzip(f32),
+    Gzip,
+    Deflate,
+    #[cfg(feature = "brotli")]
+    Brotli,
+    #[cfg(feature = "zstd")]
+    Zstd,
+    Identity,
 }
 
 impl EncodingType {
-    fn from_string(s: &str, q: f32) -> Option<EncodingType> {
+    /// Every coding compiled into the server, `identity` last.
+    pub fn all() -> Vec<EncodingType> {
+        vec![
+            #[cfg(feature = "zstd")]
+            EncodingType::Zstd,
+            #[cfg(feature = "brotli")]
+            EncodingType::Brotli,
+            EncodingType::Gzip,
+            EncodingType::Deflate,
+            EncodingType::Identity,
+        ]
+    }
+
+    fn from_string(s: &str) -> Option<EncodingType> {
         match s {
-            "gzip" => Some(Self::Gzip(q)),
+            "gzip" | "x-gzip" => Some(Self::Gzip),
+            "deflate" => Some(Self::Deflate),
+            #[cfg(feature = "brotli")]
+            "br" => Some(Self::Brotli),
+            #[cfg(feature = "zstd")]
+            "zstd" => Some(Self::Zstd),
+            "identity" => Some(Self::Identity),
             _ => None,
         }
     }
+
+    /// Level used when none is configured, favouring speed for dynamic
+    /// responses.
+    pub fn default_level(&self) -> u32 {
+        match self {
+            EncodingType::Gzip | EncodingType::Deflate => 6,
+            #[cfg(feature = "brotli")]
+            EncodingType::Brotli => 4,
+            #[cfg(feature = "zstd")]
+            EncodingType::Zstd => 3,
+            EncodingType::Identity => 0,
+        }
+    }
+
+    /// Compress `bytes` with this coding at `level`. `Identity` returns them
+    /// unchanged.
+    pub fn encode(&self, bytes: &[u8], level: u32) -> io::Result<Vec<u8>> {
+        match self {
+            EncodingType::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], Compression::new(level.min(9)));
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+            // HTTP's "deflate" is the zlib format, not a raw deflate stream
+            EncodingType::Deflate => {
+                let mut encoder = ZlibEncoder::new(vec![], Compression::new(level.min(9)));
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "brotli")]
+            EncodingType::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(vec![], 4096, level.min(11), 22);
+                encoder.write_all(bytes)?;
+                Ok(encoder.into_inner())
+            }
+            #[cfg(feature = "zstd")]
+            EncodingType::Zstd => zstd::stream::encode_all(bytes, level.min(22) as i32),
+            EncodingType::Identity => Ok(bytes.to_vec()),
+        }
+    }
+}
+
+impl EncodingType {
+    /// Wrap `reader` so it yields the body compressed with this coding,
+    /// compressing only as much as each read asks for.
+    pub fn encoder<'a, R: Read + Send + 'a>(
+        &self,
+        reader: R,
+        level: u32,
+    ) -> io::Result<Box<dyn Read + Send + 'a>> {
+        Ok(match self {
+            EncodingType::Gzip => Box::new(flate_read::GzEncoder::new(
+                reader,
+                Compression::new(level.min(9)),
+            )),
+            EncodingType::Deflate => Box::new(flate_read::ZlibEncoder::new(
+                reader,
+                Compression::new(level.min(9)),
+            )),
+            #[cfg(feature = "brotli")]
+            EncodingType::Brotli => Box::new(brotli::CompressorReader::new(
+                reader,
+                4096,
+                level.min(11),
+                22,
+            )),
+            #[cfg(feature = "zstd")]
+            EncodingType::Zstd => Box::new(zstd::stream::read::Encoder::new(
+                reader,
+                level.min(22) as i32,
+            )?),
+            EncodingType::Identity => Box::new(reader),
+        })
+    }
+
+    /// Wrap `reader` so it yields the decoded form of a body in this coding.
+    pub fn decoder<'a, R: Read + Send + 'a>(
+        &self,
+        reader: R,
+    ) -> io::Result<Box<dyn Read + Send + 'a>> {
+        Ok(match self {
+            EncodingType::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            EncodingType::Deflate => Box::new(ZlibDecoder::new(reader)),
+            #[cfg(feature = "brotli")]
+            EncodingType::Brotli => Box::new(brotli::Decompressor::new(reader, 4096)),
+            #[cfg(feature = "zstd")]
+            EncodingType::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+            EncodingType::Identity => Box::new(reader),
+        })
+    }
+}
+
+/// Why a request body could not be decoded.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// A coding in `Content-Encoding` that the server doesn't implement.
+    Unsupported(String),
+    /// The decoded body grew past the configured limit.
+    TooLarge,
+    Corrupt(io::Error),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Unsupported(coding) => {
+                write!(f, "unsupported content coding `{}`", coding)
+            }
+            DecodeError::TooLarge => write!(f, "decoded body too large"),
+            DecodeError::Corrupt(err) => write!(f, "corrupt encoded body: {}", err),
+        }
+    }
+}
+
+impl error::Error for DecodeError {}
+
+impl From<io::Error> for DecodeError {
+    fn from(err: io::Error) -> Self {
+        // a `DecodingReader` over the limit carries the reason inside
+        match err.get_ref().and_then(|e| e.downcast_ref::<DecodeError>()) {
+            Some(DecodeError::TooLarge) => DecodeError::TooLarge,
+            _ => DecodeError::Corrupt(err),
+        }
+    }
 }
 
+/// The codings listed in a `Content-Encoding` header, in the order they
+/// were applied. Fails on the first one the server doesn't implement.
+pub fn parse_content_encoding(content_encoding: &str) -> Result<Vec<EncodingType>, DecodeError> {
+    content_encoding
+        .split(',')
+        .map(|c| c.trim().to_lowercase())
+        .filter(|c| !c.is_empty())
+        .map(|c| EncodingType::from_string(&c).ok_or(DecodeError::Unsupported(c)))
+        .collect()
+}
+
+/// Undo the codings listed in a `Content-Encoding` header, last applied
+/// first, refusing to produce more than `limit` bytes so a small compressed
+/// payload can't expand without bound.
+pub fn decode_content(
+    content_encoding: &str,
+    body: Vec<u8>,
+    limit: usize,
+) -> Result<Vec<u8>, DecodeError> {
+    let mut decoded = Vec::new();
+    DecodingReader::new(&body[..], parse_content_encoding(content_encoding)?, limit)
+        .read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+
+/// Decodes a body from its content codings as it is read, so a body
+/// streamed from the connection never has to be held whole.
+///
+/// The decoders are only set up on the first read, as some of them read a
+/// header straight away. Producing more than `limit` bytes fails with an
+/// `io::Error` carrying `DecodeError::TooLarge`; data the decoders can't
+/// make sense of fails with `InvalidData` or `InvalidInput`.
+pub struct DecodingReader<'a> {
+    source: Option<Box<dyn Read + Send + 'a>>,
+    codings: Vec<EncodingType>,
+    decoder: Option<Box<dyn Read + Send + 'a>>,
+    limit: u64,
+    produced: u64,
+}
+
+impl<'a> DecodingReader<'a> {
+    pub fn new<R: Read + Send + 'a>(reader: R, codings: Vec<EncodingType>, limit: usize) -> Self {
+        Self {
+            source: Some(Box::new(reader)),
+            codings,
+            decoder: None,
+            limit: limit as u64,
+            produced: 0,
+        }
+    }
+
+    fn decoder(&mut self) -> io::Result<&mut Box<dyn Read + Send + 'a>> {
+        if self.decoder.is_none() {
+            let mut reader = self.source.take().expect("decoder is set up once");
+            for coding in self.codings.iter().rev() {
+                reader = coding.decoder(reader)?;
+            }
+            self.decoder = Some(reader);
+        }
+        Ok(self.decoder.as_mut().expect("decoder was just set up"))
+    }
+}
+
+impl Read for DecodingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // one byte past the limit is enough to know it was exceeded
+        let max = buf.len().min(
+            (self.limit.saturating_add(1).saturating_sub(self.produced))
+                .try_into()
+                .unwrap_or(usize::MAX),
+        );
+        let n = self.decoder()?.read(&mut buf[..max])?;
+        self.produced += n as u64;
+        if self.produced > self.limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                DecodeError::TooLarge,
+            ));
+        }
+        Ok(n)
+    }
+}
+
+impl fmt::Debug for DecodingReader<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DecodingReader")
+            .field("codings", &self.codings)
+            .field("limit", &self.limit)
+            .field("produced", &self.produced)
+            .finish()
+    }
+}
+
+/// Which codings the server offers, in preference order, and the level to
+/// compress each with.
+#[derive(Debug, Clone)]
+pub struct EncodingConfig {
+    preference: Vec<EncodingType>,
+    levels: HashMap<EncodingType, u32>,
+}
+
+impl Default for EncodingConfig {
+    fn default() -> Self {
+        Self {
+            preference: vec![
+                #[cfg(feature = "zstd")]
+                EncodingType::Zstd,
+                #[cfg(feature = "brotli")]
+                EncodingType::Brotli,
+                EncodingType::Gzip,
+                EncodingType::Deflate,
+            ],
+            levels: HashMap::new(),
+        }
+    }
+}
+
+impl EncodingConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the offered codings. Earlier entries win q-value ties.
+    pub fn set_preference(&mut self, preference: &[EncodingType]) {
+        self.preference = preference.to_vec();
+    }
+
+    pub fn get_preference(&self) -> &[EncodingType] {
+        &self.preference
+    }
+
+    pub fn set_level(&mut self, encoding_type: EncodingType, level: u32) {
+        self.levels.insert(encoding_type, level);
+    }
+
+    pub fn get_level(&self, encoding_type: EncodingType) -> u32 {
+        self.levels
+            .get(&encoding_type)
+            .copied()
+            .unwrap_or_else(|| encoding_type.default_level())
+    }
+
+    pub fn encode(&self, encoding_type: EncodingType, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        encoding_type.encode(bytes, self.get_level(encoding_type))
+    }
+}
+
+impl std::fmt::Display for EncodingType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncodingType::Gzip => write!(f, "gzip"),
+            EncodingType::Deflate => write!(f, "deflate"),
+            #[cfg(feature = "brotli")]
+            EncodingType::Brotli => write!(f, "br"),
+            #[cfg(feature = "zstd")]
+            EncodingType::Zstd => write!(f, "zstd"),
+            EncodingType::Identity => write!(f, "identity"),
+        }
+    }
+}
+
+/// Returned by `Encoding::negotiate` when the client excluded every coding
+/// the server could send, including `identity`.
+#[derive(Debug)]
+pub struct NotAcceptable;
+
+/// One `coding;q=value` entry of an `Accept-Encoding` header.
 #[derive(Debug, Clone)]
 pub struct Encoding {
-    encoding_type: EncodingType,
+    coding: String,
+    q: f32,
 }
 
 impl std::fmt::Display for Encoding {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.encoding_type {
-            EncodingType::Gzip(_) => write!(f, "gzip"),
-        }
+        write!(f, "{};q={}", self.coding, self.q)
     }
 }
 
 impl Encoding {
-    pub fn get_endoing_scheme(s: &str) -> Option<Encoding> {
+    /// Parse an `Accept-Encoding` value. Entries with a malformed q-value are
+    /// dropped.
+    pub fn parse_header(s: &str) -> Vec<Encoding> {
         // gzip;q=1.0, deflate
-        let encodings = s.split(",").filter(|x| !x.is_empty()).map(|x| x.trim());
-        let encodings = encodings
-            .map(|enc| {
-                let enc_type = if let Some((enc_type, q)) = enc.split_once(";") {
-                    let val = if let Some((_, value)) = q.split_once("=") {
-                        value.parse::<f32>().unwrap_or(1.0)
-                    } else {
-                        1.0
-                    };
-                    EncodingType::from_string(enc_type, val)
-                } else {
-                    EncodingType::from_string(enc, 1.0)
-                };
-
-                if let Some(t) = enc_type {
-                    Some(Encoding { encoding_type: t })
-                } else {
-                    None
+        s.split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .filter_map(|enc| {
+                let mut parts = enc.split(';');
+                let coding = parts.next().unwrap_or("").trim().to_lowercase();
+                let mut q = 1.0;
+                for param in parts {
+                    if let Some((name, value)) = param.split_once('=') {
+                        if name.trim().eq_ignore_ascii_case("q") {
+                            q = value.trim().parse::<f32>().ok()?;
+                        }
+                    }
+                }
+                if !(0.0..=1.0).contains(&q) {
+                    return None;
                 }
+                Some(Encoding { coding, q })
             })
-            .filter(|x| x.is_some()) // only accept supported compression techniques
-            .collect::<Vec<Option<Encoding>>>();
+            .collect()
+    }
+
+    /// Choose a coding for the response per RFC 9110 section 12.5.3.
+    ///
+    /// `supported` is in server preference order, which breaks ties between
+    /// equal q-values. `identity` is acceptable unless excluded by
+    /// `identity;q=0` or by `*;q=0` without an explicit `identity` entry, and
+    /// loses to any coding the client explicitly asked for.
+    pub fn negotiate(
+        accept: Option<&str>,
+        supported: &[EncodingType],
+    ) -> Result<EncodingType, NotAcceptable> {
+        let accept = match accept {
+            Some(accept) => accept,
+            // no preference expressed, so don't spend time compressing
+            None => return Ok(EncodingType::Identity),
+        };
+        let entries = Encoding::parse_header(accept);
+
+        let explicit_q = |encoding_type: EncodingType| {
+            entries
+                .iter()
+                .find(|e| EncodingType::from_string(&e.coding) == Some(encoding_type))
+                .map(|e| e.q)
+        };
+        let wildcard_q = entries.iter().find(|e| e.coding == "*").map(|e| e.q);
+
+        let mut best: Option<(EncodingType, f32)> = None;
+        for &encoding_type in supported.iter().filter(|&&e| e != EncodingType::Identity) {
+            let q = explicit_q(encoding_type).or(wildcard_q).unwrap_or(0.0);
+            if q > 0.0 && !matches!(best, Some((_, best_q)) if best_q >= q) {
+                best = Some((encoding_type, q));
+            }
+        }
 
-        let result = encodings.first();
-        match result {
-            Some(x) => x.clone(),
-            None => None,
+        // when unlisted, identity is acceptable but never preferred over a listed coding
+        let identity_q = explicit_q(EncodingType::Identity)
+            .or(wildcard_q)
+            .unwrap_or(f32::MIN_POSITIVE);
+        match best {
+            Some((encoding_type, q)) if q >= identity_q => Ok(encoding_type),
+            _ if identity_q > 0.0 => Ok(EncodingType::Identity),
+            _ => Err(NotAcceptable),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    const SUPPORTED: &[EncodingType] = &[EncodingType::Gzip, EncodingType::Deflate];
+
+    /// The coding chosen for `accept`, or `None` for a 406.
+    fn negotiate(accept: &str) -> Option<EncodingType> {
+        Encoding::negotiate(Some(accept), SUPPORTED).ok()
+    }
+
+    #[test]
+    fn no_header_means_identity() {
+        let chosen = Encoding::negotiate(None, SUPPORTED).ok();
+        assert_eq!(chosen, Some(EncodingType::Identity));
+        assert_eq!(negotiate(""), Some(EncodingType::Identity));
+    }
+
+    #[test]
+    fn prefers_the_highest_q() {
+        assert_eq!(
+            negotiate("gzip;q=0.5, deflate;q=0.8"),
+            Some(EncodingType::Deflate)
+        );
+        assert_eq!(negotiate("deflate;q=0.2, gzip"), Some(EncodingType::Gzip));
+        // an explicitly preferred identity beats compression
+        assert_eq!(
+            negotiate("gzip;q=0.5, identity"),
+            Some(EncodingType::Identity)
+        );
+        // but an unlisted one loses to any listed coding
+        assert_eq!(negotiate("gzip;q=0.001"), Some(EncodingType::Gzip));
+    }
+
+    #[test]
+    fn q_zero_excludes_a_coding() {
+        assert_eq!(negotiate("gzip;q=0, deflate"), Some(EncodingType::Deflate));
+        assert_eq!(negotiate("gzip;q=0"), Some(EncodingType::Identity));
+        // a malformed q-value drops the entry rather than accepting it
+        assert_eq!(negotiate("gzip;q=2"), Some(EncodingType::Identity));
+    }
+
+    #[test]
+    fn wildcard_covers_unlisted_codings() {
+        assert_eq!(negotiate("*"), Some(EncodingType::Gzip));
+        assert_eq!(negotiate("*;q=0.5, gzip;q=0"), Some(EncodingType::Deflate));
+        assert_eq!(negotiate("*;q=0, identity"), Some(EncodingType::Identity));
+        assert_eq!(negotiate("*;q=0"), None);
+    }
+
+    #[test]
+    fn identity_can_be_refused() {
+        assert_eq!(negotiate("identity;q=0, gzip"), Some(EncodingType::Gzip));
+        assert_eq!(negotiate("identity;q=0"), None);
+        assert_eq!(negotiate("identity;q=0, gzip;q=0, deflate;q=0"), None);
+        assert_eq!(negotiate("identity;q=0, br"), None);
+    }
+
+    #[test]
+    fn ties_go_to_the_server_preference() {
+        assert_eq!(negotiate("deflate, gzip"), Some(EncodingType::Gzip));
+        assert_eq!(
+            negotiate("deflate;q=0.5, gzip;q=0.5"),
+            Some(EncodingType::Gzip)
+        );
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn default_preference_favours_brotli_over_gzip() {
+        let config = EncodingConfig::default();
+        let chosen = Encoding::negotiate(Some("gzip, br"), config.get_preference()).ok();
+        assert_eq!(chosen, Some(EncodingType::Brotli));
+    }
+
+    #[test]
+    fn decodes_within_the_limit() {
+        let decoded = decode_content("gzip", gzip(b"hello"), 5).unwrap();
+        assert_eq!(decoded, b"hello");
+        // an unbounded limit must not overflow
+        let decoded = decode_content("identity", b"hello".to_vec(), usize::MAX).unwrap();
+        assert_eq!(decoded, b"hello");
+    }
+
+    #[test]
+    fn refuses_to_decode_past_the_limit() {
+        let err = decode_content("gzip", gzip(&[0; 4096]), 4095).unwrap_err();
+        assert!(matches!(err, DecodeError::TooLarge));
+    }
+}
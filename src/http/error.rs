@@ -1,4 +1,13 @@
 #[derive(Debug)]
 pub enum HTTPError {
     Custom,
+    /// The request uses a transfer coding the server doesn't implement.
+    NotImplemented,
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for HTTPError {
+    fn from(err: std::io::Error) -> Self {
+        HTTPError::Io(err)
+    }
 }
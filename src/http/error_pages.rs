@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+use super::{
+    into_response::IntoResponse,
+    request::{Request, RequestBody},
+    response::{Response, StatusCode},
+};
+
+/// Body format for a rendered error, negotiated from the `Accept` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Text,
+    Html,
+    Json,
+}
+
+impl ErrorFormat {
+    // in server preference order, used when q-values tie
+    const MEDIA_TYPES: [(&'static str, ErrorFormat); 3] = [
+        ("text/plain", ErrorFormat::Text),
+        ("text/html", ErrorFormat::Html),
+        ("application/json", ErrorFormat::Json),
+    ];
+
+    /// Pick the format the client prefers, falling back to plain text.
+    pub fn from_accept(accept: Option<&str>) -> ErrorFormat {
+        let accept = match accept {
+            Some(accept) => accept,
+            None => return ErrorFormat::Text,
+        };
+
+        // (media range, q)
+        let ranges: Vec<(String, f32)> = accept
+            .split(',')
+            .map(|range| range.trim())
+            .filter(|range| !range.is_empty())
+            .map(|range| {
+                let mut parts = range.split(';');
+                let media = parts.next().unwrap_or("").trim().to_lowercase();
+                let q = parts
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (media, q)
+            })
+            .collect();
+
+        let quality = |media_type: &str| -> f32 {
+            let main_type = media_type.split('/').next().unwrap_or("");
+            // the most specific matching range decides the quality
+            let exact = ranges.iter().find(|(range, _)| range == media_type);
+            let wildcard_subtype = ranges
+                .iter()
+                .find(|(range, _)| range.strip_suffix("/*") == Some(main_type));
+            let wildcard = ranges.iter().find(|(range, _)| range == "*/*");
+            exact
+                .or(wildcard_subtype)
+                .or(wildcard)
+                .map(|(_, q)| *q)
+                .unwrap_or(0.0)
+        };
+
+        let mut best = (ErrorFormat::Text, 0.0);
+        for (media_type, format) in Self::MEDIA_TYPES {
+            let q = quality(media_type);
+            if q > best.1 {
+                best = (format, q);
+            }
+        }
+        best.0
+    }
+
+    /// Render the default error body for `status` in this format.
+    pub fn render(&self, status: StatusCode) -> Response {
+        let (content_type, body) = match self {
+            ErrorFormat::Text => ("text/plain", status.to_string()),
+            ErrorFormat::Html => (
+                "text/html",
+                format!(
+                    "<!DOCTYPE html>\n<html>\n<head><title>{0}</title></head>\n<body><h1>{0}</h1></body>\n</html>\n",
+                    status
+                ),
+            ),
+            ErrorFormat::Json => (
+                "application/json",
+                format!(
+                    r#"{{"status":{},"error":"{}"}}"#,
+                    status.code(),
+                    status.reason()
+                ),
+            ),
+        };
+        (status, [("Content-Type", content_type)], body).into_response()
+    }
+}
+
+type ErrorRenderer = Box<dyn Fn(StatusCode, ErrorFormat) -> Response + Send + Sync>;
+
+/// Renders bodies for error responses that don't already have one.
+#[derive(Default)]
+pub struct ErrorPages {
+    renderers: HashMap<StatusCode, ErrorRenderer>,
+}
+
+impl std::fmt::Debug for ErrorPages {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ErrorPages")
+            .field("renderers", &self.renderers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl ErrorPages {
+    pub fn new() -> Self {
+        Self {
+            renderers: HashMap::new(),
+        }
+    }
+
+    pub fn set_renderer<F, R>(&mut self, status: StatusCode, f: F)
+    where
+        F: Fn(StatusCode, ErrorFormat) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.renderers.insert(
+            status,
+            Box::new(move |status, format| f(status, format).into_response()),
+        );
+    }
+
+    pub fn render(&self, status: StatusCode, req: Option<&Request>) -> Response {
+        let accept = req.and_then(|req| req.get_headers().get("accept"));
+        let format = ErrorFormat::from_accept(accept.map(|a| a.as_str()));
+        match self.renderers.get(&status) {
+            Some(renderer) => {
+                let mut res = renderer(status, format);
+                res.set_status_code(status);
+                res
+            }
+            None => format.render(status),
+        }
+    }
+
+    /// Fill in the body of an error response that has none, keeping its
+    /// status and any headers and cookies the handler set (e.g. `Allow`).
+    pub fn apply(&self, res: Response, req: Option<&Request>) -> Response {
+        let status = res.get_status_code();
+        let has_body =
+            matches!(res.get_body(), Some(RequestBody::String(bytes)) if !bytes.is_empty());
+        if !status.is_error() || has_body {
+            return res;
+        }
+
+        let mut rendered = self.render(status, req);
+        let mut headers = res.get_headers();
+        headers.extend(rendered.get_headers());
+        rendered.set_headers(headers);
+        for cookie in res.get_cookies() {
+            rendered.add_cookie(cookie.clone());
+        }
+        rendered
+    }
+}
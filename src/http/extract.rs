@@ -0,0 +1,294 @@
+use std::{collections::HashMap, fmt, io::Read};
+
+use serde::de::DeserializeOwned;
+
+use super::{
+    cookie::CookieJar,
+    into_response::IntoResponse,
+    multipart::{Multipart, MultipartError},
+    request::{BodyReader, Request, RequestBody},
+    response::{Response, StatusCode},
+};
+
+/// A typed value resolved from the request before a handler is invoked.
+///
+/// Handlers declare extractors as arguments; when one fails its `Rejection`
+/// is sent to the client and the handler is never called.
+pub trait FromRequest: Sized {
+    fn from_request(req: &Request) -> Result<Self, Rejection>;
+}
+
+/// Why an extractor could not be resolved.
+#[derive(Debug)]
+pub struct Rejection {
+    status: StatusCode,
+    message: String,
+}
+
+impl Rejection {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BadRequest, message)
+    }
+
+    pub fn get_status_code(&self) -> StatusCode {
+        self.status
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        (self.status, self.message).into_response()
+    }
+}
+
+/// Route params (`/files/{file_name}`) deserialized into `T`.
+///
+/// `T` can be a struct or map keyed by param name, or a single value when the
+/// route has exactly one param.
+#[derive(Debug)]
+pub struct Path<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Path<T> {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        let pairs: Vec<(String, String)> = req.get_params().into_iter().collect();
+        let encoded = serde_urlencoded::to_string(&pairs)
+            .map_err(|err| Rejection::bad_request(format!("Invalid path params: {}", err)))?;
+
+        serde_urlencoded::from_str::<T>(&encoded)
+            .or_else(|err| match pairs.len() {
+                1 => serde_urlencoded::from_str::<Vec<(String, T)>>(&encoded)
+                    .map(|mut values| values.pop().unwrap().1),
+                _ => Err(err),
+            })
+            .map(Path)
+            .map_err(|err| Rejection::bad_request(format!("Invalid path params: {}", err)))
+    }
+}
+
+/// The query string deserialized into `T`.
+#[derive(Debug)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        let query = req.get_query().unwrap_or_default();
+        serde_urlencoded::from_str::<T>(&query)
+            .map(Query)
+            .map_err(|err| Rejection::bad_request(format!("Invalid query string: {}", err)))
+    }
+}
+
+/// An `application/x-www-form-urlencoded` body deserialized into `T`.
+#[derive(Debug)]
+pub struct Form<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Form<T> {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        let content_type = req
+            .get_headers()
+            .get("content-type")
+            .map(|ct| ct.split(';').next().unwrap_or("").trim().to_lowercase());
+        if content_type.as_deref() != Some("application/x-www-form-urlencoded") {
+            return Err(Rejection::new(
+                StatusCode::UnsupportedMediaType,
+                "Expected `Content-Type: application/x-www-form-urlencoded`",
+            ));
+        }
+
+        let Body(bytes) = Body::from_request(req)?;
+        serde_urlencoded::from_bytes::<T>(&bytes)
+            .map(Form)
+            .map_err(|err| Rejection::bad_request(format!("Invalid form body: {}", err)))
+    }
+}
+
+/// A JSON body deserialized into `T`. As a handler's return value, `T` is
+/// serialized into an `application/json` response.
+#[cfg(feature = "json")]
+#[derive(Debug)]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "json")]
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        let media_type = req
+            .get_headers()
+            .get("content-type")
+            .map(|ct| ct.split(';').next().unwrap_or("").trim().to_lowercase());
+        // `application/problem+json` and friends are JSON too
+        let is_json = media_type
+            .as_deref()
+            .is_some_and(|mt| mt == "application/json" || mt.ends_with("+json"));
+        if !is_json {
+            return Err(Rejection::new(
+                StatusCode::UnsupportedMediaType,
+                "Expected `Content-Type: application/json`",
+            ));
+        }
+
+        let Body(bytes) = Body::from_request(req)?;
+        serde_json::from_slice::<T>(&bytes)
+            .map(Json)
+            .map_err(|err| Rejection::bad_request(format!("Invalid JSON body: {}", err)))
+    }
+}
+
+/// The raw request body. On a streaming route it is read from the
+/// connection in full.
+#[derive(Debug)]
+pub struct Body(pub Vec<u8>);
+
+impl FromRequest for Body {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        if req.has_body_reader() {
+            let mut bytes = Vec::new();
+            req.take_body_reader()
+                .read_to_end(&mut bytes)
+                .map_err(|err| Rejection::bad_request(format!("Incomplete body: {}", err)))?;
+            return Ok(Body(bytes));
+        }
+        match req.get_body() {
+            Some(RequestBody::String(bytes)) => Ok(Body(bytes)),
+            None => Ok(Body(Vec::new())),
+        }
+    }
+}
+
+/// The request body as a stream. On a route registered with
+/// `add_streaming_route` it reads from the connection; elsewhere it reads the
+/// buffered body.
+impl FromRequest for BodyReader {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        Ok(req.take_body_reader())
+    }
+}
+
+/// A `multipart/form-data` body, parsed as it is read.
+impl FromRequest for Multipart {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        Multipart::from_request(req).map_err(|err| {
+            let status = match err {
+                MultipartError::NotMultipart => StatusCode::UnsupportedMediaType,
+                _ => StatusCode::BadRequest,
+            };
+            Rejection::new(status, err.to_string())
+        })
+    }
+}
+
+/// The cookies sent with the request.
+#[derive(Debug)]
+pub struct Cookies(pub CookieJar);
+
+impl FromRequest for Cookies {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        Ok(Cookies(req.get_cookies()))
+    }
+}
+
+/// All request headers, keyed by lowercase name.
+#[derive(Debug)]
+pub struct Headers(pub HashMap<String, String>);
+
+impl FromRequest for Headers {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        Ok(Headers(req.get_headers().clone()))
+    }
+}
+
+macro_rules! header_extractor {
+    ($(#[$doc:meta])* $name:ident, $header:literal) => {
+        $(#[$doc])*
+        #[derive(Debug)]
+        pub struct $name(pub String);
+
+        impl FromRequest for $name {
+            fn from_request(req: &Request) -> Result<Self, Rejection> {
+                req.get_headers()
+                    .get($header)
+                    .map(|value| $name(value.clone()))
+                    .ok_or_else(|| {
+                        Rejection::bad_request(concat!("Missing `", $header, "` header"))
+                    })
+            }
+        }
+    };
+}
+
+header_extractor!(
+    /// The `User-Agent` header.
+    UserAgent,
+    "user-agent"
+);
+header_extractor!(
+    /// The `Host` header.
+    Host,
+    "host"
+);
+header_extractor!(
+    /// The `Content-Type` header.
+    ContentType,
+    "content-type"
+);
+header_extractor!(
+    /// The `Accept` header.
+    Accept,
+    "accept"
+);
+header_extractor!(
+    /// The `Authorization` header.
+    Authorization,
+    "authorization"
+);
+
+/// Application state registered with `Server::add_state`.
+#[derive(Debug)]
+pub struct State<T>(pub T);
+
+impl<T: Clone + Send + Sync + 'static> FromRequest for State<T> {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        req.get_state::<T>().cloned().map(State).ok_or_else(|| {
+            Rejection::new(
+                StatusCode::InternalServerError,
+                format!("Missing state `{}`", std::any::type_name::<T>()),
+            )
+        })
+    }
+}
+
+/// A per-request extension attached by middleware.
+#[derive(Debug)]
+pub struct Extension<T>(pub T);
+
+impl<T: Clone + Send + Sync + 'static> FromRequest for Extension<T> {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        req.get_extension::<T>()
+            .cloned()
+            .map(Extension)
+            .ok_or_else(|| {
+                Rejection::new(
+                    StatusCode::InternalServerError,
+                    format!("Missing extension `{}`", std::any::type_name::<T>()),
+                )
+            })
+    }
+}
+
+impl<T: FromRequest> FromRequest for Option<T> {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        Ok(T::from_request(req).ok())
+    }
+}
@@ -0,0 +1,514 @@
+use std::{
+    fmt, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use httpdate::HttpDate;
+
+use super::{
+    compression,
+    conditional::{self, ETag, Validators},
+    encoding::{Encoding, EncodingType},
+    into_response::IntoResponse,
+    range::{self, ByteRange, ByteRanges, RangeNotSatisfiable},
+    request::Request,
+    response::{Response, StatusCode},
+    safe_path::{resolve_path, PathError},
+};
+
+/// Extensions of precompressed siblings, in server preference order.
+const PRECOMPRESSED: &[(&str, EncodingType)] = &[
+    #[cfg(feature = "brotli")]
+    ("br", EncodingType::Brotli),
+    ("gz", EncodingType::Gzip),
+];
+
+/// Content types by file extension. Anything else is served as
+/// `application/octet-stream`.
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("ico", "image/x-icon"),
+    ("pdf", "application/pdf"),
+    ("wasm", "application/wasm"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("ogg", "audio/ogg"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+];
+
+/// The content type for `path`, guessed from its extension.
+pub fn mime_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase());
+    ext.and_then(|ext| {
+        MIME_TYPES
+            .iter()
+            .find(|(known, _)| *known == ext)
+            .map(|(_, mime)| *mime)
+    })
+    .unwrap_or("application/octet-stream")
+}
+
+/// Precompressed siblings of `path` (`file.br`, `file.gz`) that are at least
+/// as new as the file itself, so a stale build artifact is never served.
+fn precompressed_variants(path: &Path) -> Vec<(PathBuf, EncodingType)> {
+    let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+    let original_modified = modified(path);
+
+    PRECOMPRESSED
+        .iter()
+        .filter_map(|(ext, encoding_type)| {
+            let mut sibling = path.as_os_str().to_owned();
+            sibling.push(".");
+            sibling.push(ext);
+            let sibling = PathBuf::from(sibling);
+            let fresh = match (modified(&sibling), original_modified) {
+                (Some(sibling_modified), Some(original)) => sibling_modified >= original,
+                _ => false,
+            };
+            (sibling.is_file() && fresh).then_some((sibling, *encoding_type))
+        })
+        .collect()
+}
+
+/// Respond with the file at `path`, streaming it from disk.
+///
+/// When a precompressed sibling exists in a coding the client's
+/// `Accept-Encoding` permits, its bytes are sent instead with the matching
+/// `Content-Encoding`, so the server doesn't compress the file again.
+///
+/// A `Range` header is honoured with `206 Partial Content`, as a
+/// `multipart/byteranges` body when it names several ranges, or
+/// `416 Range Not Satisfiable`. Ranges select from the bytes actually sent,
+/// so they apply to the precompressed sibling when one is chosen.
+///
+/// The response carries an `ETag` and `Last-Modified`, which the server
+/// checks conditional requests against.
+pub fn file_response(path: &Path, req: &Request) -> io::Result<Response> {
+    if !path.is_file() {
+        return Err(io::Error::from(io::ErrorKind::NotFound));
+    }
+
+    let variants = precompressed_variants(path);
+    let offered = variants.iter().map(|(_, e)| *e).collect::<Vec<_>>();
+    let accept_encoding = req.get_headers().get("accept-encoding");
+    let chosen = Encoding::negotiate(accept_encoding.map(|a| a.as_str()), &offered)
+        .ok()
+        .and_then(|encoding_type| variants.iter().find(|(_, e)| *e == encoding_type));
+
+    let (file_path, content_encoding) = match chosen {
+        Some((sibling, encoding_type)) => (sibling.as_path(), Some(encoding_type)),
+        None => (path, None),
+    };
+    let metadata = fs::metadata(file_path)?;
+    let length = metadata.len();
+    // each coding is its own representation, so the tag follows the file sent
+    let etag = ETag::from_metadata(&metadata);
+    let last_modified = fs::metadata(path)?.modified().ok();
+    let content_type = mime_type(path);
+
+    let mut res = StatusCode::Ok.into_response();
+    res.set_header("Accept-Ranges", "bytes");
+    res.set_header("ETag", &etag.to_string());
+    if let Some(modified) = last_modified {
+        res.set_header("Last-Modified", &HttpDate::from(modified).to_string());
+    }
+    if let Some(encoding_type) = content_encoding {
+        res.set_header("Content-Encoding", &encoding_type.to_string());
+    }
+    if !variants.is_empty() {
+        compression::add_vary(&mut res, "Accept-Encoding");
+    }
+
+    match requested_ranges(req, length, &etag, last_modified) {
+        Ok(None) => {
+            res.set_header("Content-Type", content_type);
+            res.set_header("Content-Length", &length.to_string());
+            res.set_stream(fs::File::open(file_path)?);
+        }
+        Ok(Some(ranges)) if ranges.len() == 1 => {
+            let range = ranges[0];
+            res.set_status_code(StatusCode::PartialContent);
+            res.set_header("Content-Type", content_type);
+            res.set_header("Content-Range", &range.content_range(length));
+            res.set_header("Content-Length", &range.len().to_string());
+            res.set_stream(range::range_reader(file_path, range)?);
+        }
+        Ok(Some(ranges)) => {
+            let body = ByteRanges::new(&ranges, content_type, length);
+            res.set_status_code(StatusCode::PartialContent);
+            res.set_header("Content-Type", &body.content_type());
+            res.set_header("Content-Length", &body.content_length().to_string());
+            res.set_stream(body.reader(file_path)?);
+        }
+        Err(RangeNotSatisfiable) => {
+            res.set_status_code(StatusCode::RangeNotSatisfiable);
+            res.set_header("Content-Range", &format!("bytes */{}", length));
+        }
+    }
+    Ok(res)
+}
+
+/// The ranges `req` asks for, or `None` when the whole file should be sent:
+/// there is no `Range` header, or `If-Range` names a different version.
+fn requested_ranges(
+    req: &Request,
+    length: u64,
+    etag: &ETag,
+    last_modified: Option<SystemTime>,
+) -> Result<Option<Vec<ByteRange>>, RangeNotSatisfiable> {
+    let headers = req.get_headers();
+    let range = match headers.get("range") {
+        Some(range) => range,
+        None => return Ok(None),
+    };
+    if let Some(if_range) = headers.get("if-range") {
+        if !if_range_matches(if_range, etag, last_modified) {
+            return Ok(None);
+        }
+    }
+    range::parse_range(range, length)
+}
+
+/// Whether the validator in `If-Range` still describes the file: a strongly
+/// matching entity tag, or exactly the `Last-Modified` date.
+fn if_range_matches(if_range: &str, etag: &ETag, last_modified: Option<SystemTime>) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return if_range
+            .parse::<ETag>()
+            .is_ok_and(|tag| tag.strong_eq(etag));
+    }
+    match (if_range.parse::<HttpDate>(), last_modified) {
+        (Ok(date), Some(modified)) => date == HttpDate::from(modified),
+        _ => false,
+    }
+}
+
+/// Why a file could not be written or removed.
+#[derive(Debug)]
+pub enum FileError {
+    Path(PathError),
+    /// The target's parent is missing or not a directory, or a file was
+    /// expected where there is a directory.
+    Conflict,
+    Io(io::Error),
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileError::Path(err) => write!(f, "{}", err),
+            FileError::Conflict => write!(f, "conflicts with the existing tree"),
+            FileError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<PathError> for FileError {
+    fn from(err: PathError) -> Self {
+        FileError::Path(err)
+    }
+}
+
+impl From<io::Error> for FileError {
+    fn from(err: io::Error) -> Self {
+        FileError::Io(err)
+    }
+}
+
+impl IntoResponse for FileError {
+    fn into_response(self) -> Response {
+        match self {
+            FileError::Path(err) => err.into_response(),
+            FileError::Conflict => StatusCode::Conflict.into_response(),
+            FileError::Io(err) => err.into_response(),
+        }
+    }
+}
+
+/// Write `bytes` to `path`, replacing any existing file, and return whether
+/// the file was created. See `write_file_from`.
+pub fn write_file(path: &Path, bytes: &[u8], create_dirs: bool) -> Result<bool, FileError> {
+    write_file_from(path, bytes, create_dirs)
+}
+
+/// Copy `reader` to `path`, replacing any existing file, and return whether
+/// the file was created.
+///
+/// The bytes go to a temporary file beside the target that is renamed over
+/// it once `reader` is exhausted, so readers never see a partial file and a
+/// failed or cut-off upload leaves nothing behind. Missing parent
+/// directories are created when `create_dirs` is set and are a conflict
+/// otherwise.
+pub fn write_file_from<R: Read>(
+    path: &Path,
+    mut reader: R,
+    create_dirs: bool,
+) -> Result<bool, FileError> {
+    if path.is_dir() {
+        return Err(FileError::Conflict);
+    }
+    let parent = path.parent().ok_or(FileError::Conflict)?;
+    if !parent.exists() && create_dirs {
+        fs::create_dir_all(parent).map_err(|err| match err.kind() {
+            // some ancestor is a file
+            io::ErrorKind::AlreadyExists => FileError::Conflict,
+            _ => FileError::Io(err),
+        })?;
+    }
+    if !parent.is_dir() {
+        return Err(FileError::Conflict);
+    }
+
+    let created = !path.exists();
+    let tmp = temp_path(path);
+    let written = fs::File::create(&tmp).and_then(|mut file| {
+        io::copy(&mut reader, &mut file)?;
+        file.sync_all()
+    });
+    if let Err(err) = written.and_then(|_| fs::rename(&tmp, path)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(FileError::Io(err));
+    }
+    Ok(created)
+}
+
+/// A hidden sibling of `path` for `write_file` to write into first.
+fn temp_path(path: &Path) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{}-{:x}.tmp", std::process::id(), nanos));
+    path.with_file_name(name)
+}
+
+/// Serves files below a root directory; mount it with `Server::mount`.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index_files: Vec<String>,
+    directory_listing: bool,
+    writable: bool,
+    create_dirs: bool,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            index_files: vec!["index.html".to_string()],
+            directory_listing: false,
+            writable: false,
+            create_dirs: false,
+        }
+    }
+
+    /// Files tried, in order, when a directory is requested.
+    pub fn set_index_files(&mut self, index_files: &[&str]) {
+        self.index_files = index_files.iter().map(|f| f.to_string()).collect();
+    }
+
+    /// List the contents of directories that have no index file instead of
+    /// responding `404`.
+    pub fn set_directory_listing(&mut self, enabled: bool) {
+        self.directory_listing = enabled;
+    }
+
+    /// Accept `PUT` and `DELETE` below the root as well as `GET`.
+    pub fn set_writable(&mut self, writable: bool) {
+        self.writable = writable;
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Create missing intermediate directories on `PUT` instead of
+    /// responding `409`.
+    pub fn set_create_dirs(&mut self, create_dirs: bool) {
+        self.create_dirs = create_dirs;
+    }
+
+    pub fn get_root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Respond to `req` for `rel_path`, relative to the root directory.
+    /// Paths that would escape the root are refused.
+    pub fn serve(&self, rel_path: &str, req: &Request) -> Response {
+        let path = match resolve_path(&self.root, rel_path) {
+            Ok(path) => path,
+            Err(err) => return err.into_response(),
+        };
+
+        if path.is_dir() {
+            let index = self
+                .index_files
+                .iter()
+                .map(|index| path.join(index))
+                .find(|index| index.is_file());
+            return match index {
+                Some(index) => file_response(&index, req).into_response(),
+                None if self.directory_listing => {
+                    directory_listing(&path, &req.get_path()).into_response()
+                }
+                None => StatusCode::NotFound.into_response(),
+            };
+        }
+
+        file_response(&path, req).into_response()
+    }
+
+    /// Create or replace the file at `rel_path` with the request body,
+    /// streamed to disk: `201` when it is new, `204` when it replaced one.
+    pub fn put(&self, rel_path: &str, req: &Request) -> Response {
+        let path = match resolve_path(&self.root, rel_path) {
+            Ok(path) => path,
+            Err(err) => return err.into_response(),
+        };
+        if let Some(status) = conditional::evaluate(req, current_validators(&path).as_ref()) {
+            return status.into_response();
+        }
+
+        match write_file_from(&path, req.take_body_reader(), self.create_dirs) {
+            Ok(true) => StatusCode::Created.into_response(),
+            Ok(false) => StatusCode::NoContent.into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+
+    /// Remove the file or empty directory at `rel_path`. The root itself
+    /// can't be removed, and a directory with entries is a conflict.
+    pub fn delete(&self, rel_path: &str, req: &Request) -> Response {
+        let path = match resolve_path(&self.root, rel_path) {
+            Ok(path) => path,
+            Err(err) => return err.into_response(),
+        };
+        if path == self.root {
+            return StatusCode::Forbidden.into_response();
+        }
+        if let Some(status) = conditional::evaluate(req, current_validators(&path).as_ref()) {
+            return status.into_response();
+        }
+
+        let removed = match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => {
+                let empty = fs::read_dir(&path).map(|mut entries| entries.next().is_none());
+                if matches!(empty, Ok(false)) {
+                    return StatusCode::Conflict.into_response();
+                }
+                fs::remove_dir(&path)
+            }
+            Ok(_) => fs::remove_file(&path),
+            Err(err) => Err(err),
+        };
+        match removed {
+            Ok(()) => StatusCode::NoContent.into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
+/// Validators of the file at `path`, or `None` when there is no file for
+/// preconditions to be checked against.
+fn current_validators(path: &Path) -> Option<Validators> {
+    fs::metadata(path)
+        .ok()
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| Validators::from_metadata(&metadata))
+}
+
+/// An HTML page linking to every entry of `dir`, which was requested as
+/// `request_path`.
+fn directory_listing(dir: &Path, request_path: &str) -> io::Result<Response> {
+    let mut entries = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| {
+            let mut name = entry.file_name().to_string_lossy().to_string();
+            if entry.path().is_dir() {
+                name.push('/');
+            }
+            name
+        })
+        .collect::<Vec<_>>();
+    entries.sort();
+
+    let base = request_path.trim_end_matches('/');
+    let title = html_escape(&format!("Index of {}/", base));
+    let items = entries
+        .iter()
+        .map(|name| {
+            format!(
+                "<li><a href=\"{}/{}\">{}</a></li>\n",
+                html_escape(base),
+                percent_encode(name),
+                html_escape(name)
+            )
+        })
+        .collect::<String>();
+    let page = format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n<ul>\n{1}</ul>\n</body>\n</html>\n",
+        title, items
+    );
+
+    let mut res = page.into_response();
+    res.set_header("Content-Type", "text/html; charset=utf-8");
+    Ok(res)
+}
+
+fn html_escape(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#39;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
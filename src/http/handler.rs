@@ -0,0 +1,63 @@
+use super::{
+    extract::FromRequest, into_response::IntoResponse, request::Request, response::Response,
+};
+
+/// Something the router can invoke for a matched request.
+///
+/// Implemented for closures taking `&Request` and for closures whose
+/// arguments are all extractors. `Args` only exists to keep those impls apart.
+pub trait Handler<Args>: Send + Sync + 'static {
+    fn call(&self, req: &Request) -> Response;
+}
+
+/// Marker for handlers that take the raw `&Request`.
+pub struct RawRequest;
+
+impl<F, R> Handler<RawRequest> for F
+where
+    F: Fn(&Request) -> R + Send + Sync + 'static,
+    R: IntoResponse,
+{
+    fn call(&self, req: &Request) -> Response {
+        self(req).into_response()
+    }
+}
+
+impl<F, R> Handler<()> for F
+where
+    F: Fn() -> R + Send + Sync + 'static,
+    R: IntoResponse,
+{
+    fn call(&self, _req: &Request) -> Response {
+        self().into_response()
+    }
+}
+
+macro_rules! extractor_handler {
+    ($($arg:ident),+) => {
+        impl<F, R, $($arg,)+> Handler<($($arg,)+)> for F
+        where
+            F: Fn($($arg,)+) -> R + Send + Sync + 'static,
+            R: IntoResponse,
+            $($arg: FromRequest,)+
+        {
+            #[allow(non_snake_case)]
+            fn call(&self, req: &Request) -> Response {
+                $(
+                    let $arg = match $arg::from_request(req) {
+                        Ok(value) => value,
+                        Err(rejection) => return rejection.into_response(),
+                    };
+                )+
+                self($($arg,)+).into_response()
+            }
+        }
+    };
+}
+
+extractor_handler!(A1);
+extractor_handler!(A1, A2);
+extractor_handler!(A1, A2, A3);
+extractor_handler!(A1, A2, A3, A4);
+extractor_handler!(A1, A2, A3, A4, A5);
+extractor_handler!(A1, A2, A3, A4, A5, A6);
@@ -0,0 +1,154 @@
+use std::{collections::HashMap, io};
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+#[cfg(feature = "json")]
+use super::extract::Json;
+use super::{
+    encoding::DecodeError,
+    response::{Response, StatusCode},
+};
+
+/// Conversion of a handler's return value into a `Response`.
+///
+/// Implemented for `Response` itself, plain bodies, status codes, and tuples
+/// that combine a status and/or headers with a body. `Result<T, E>` converts
+/// whichever side it holds, so handlers can use `?` with any error type that
+/// implements `IntoResponse`.
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl IntoResponse for () {
+    fn into_response(self) -> Response {
+        StatusCode::Ok.into_response()
+    }
+}
+
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> Response {
+        Response::builder().status(self).build()
+    }
+}
+
+impl IntoResponse for &str {
+    fn into_response(self) -> Response {
+        Response::text(self)
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        Response::text(self)
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> Response {
+        Response::bytes(self)
+    }
+}
+
+impl IntoResponse for &[u8] {
+    fn into_response(self) -> Response {
+        Response::bytes(self)
+    }
+}
+
+impl<B: IntoResponse> IntoResponse for (StatusCode, B) {
+    fn into_response(self) -> Response {
+        let (status, body) = self;
+        let mut res = body.into_response();
+        res.set_status_code(status);
+        res
+    }
+}
+
+impl<B: IntoResponse> IntoResponse for (HashMap<String, String>, B) {
+    fn into_response(self) -> Response {
+        let (headers, body) = self;
+        let mut res = body.into_response();
+        for (key, value) in headers {
+            res.set_header(&key, &value);
+        }
+        res
+    }
+}
+
+impl<B: IntoResponse, const N: usize> IntoResponse for ([(&str, &str); N], B) {
+    fn into_response(self) -> Response {
+        let (headers, body) = self;
+        let mut res = body.into_response();
+        for (key, value) in headers {
+            res.set_header(key, value);
+        }
+        res
+    }
+}
+
+impl<B: IntoResponse> IntoResponse for (StatusCode, HashMap<String, String>, B) {
+    fn into_response(self) -> Response {
+        let (status, headers, body) = self;
+        (status, (headers, body)).into_response()
+    }
+}
+
+impl<B: IntoResponse, const N: usize> IntoResponse for (StatusCode, [(&str, &str); N], B) {
+    fn into_response(self) -> Response {
+        let (status, headers, body) = self;
+        (status, (headers, body)).into_response()
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> Response {
+        match self {
+            Ok(ok) => ok.into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        match serde_json::to_vec(&self.0) {
+            Ok(bytes) => Response::builder()
+                .header("Content-Type", "application/json")
+                .body(bytes),
+            Err(err) => {
+                eprintln!("Error in serializing JSON response: {}", err);
+                StatusCode::InternalServerError.into_response()
+            }
+        }
+    }
+}
+
+impl IntoResponse for io::Error {
+    fn into_response(self) -> Response {
+        let too_large = self
+            .get_ref()
+            .is_some_and(|e| matches!(e.downcast_ref(), Some(DecodeError::TooLarge)));
+        match self.kind() {
+            // a request body that decoded to more than is allowed
+            _ if too_large => StatusCode::PayloadTooLarge,
+            io::ErrorKind::NotFound => StatusCode::NotFound,
+            io::ErrorKind::PermissionDenied => StatusCode::Forbidden,
+            // a request body that ended before its `Content-Length`
+            io::ErrorKind::UnexpectedEof => StatusCode::BadRequest,
+            // a request body that doesn't decode from its `Content-Encoding`
+            io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput => StatusCode::BadRequest,
+            // ENOSPC; `ErrorKind::StorageFull` isn't stable on our toolchain
+            _ if self.raw_os_error() == Some(28) => StatusCode::InsufficientStorage,
+            _ => StatusCode::InternalServerError,
+        }
+        .into_response()
+    }
+}
@@ -0,0 +1,14 @@
+use super::{request::Request, response::Response};
+
+/// The rest of the chain: remaining middleware followed by the route handler.
+pub type Next<'a> = &'a dyn Fn(&mut Request) -> Response;
+
+pub type Middleware = Box<dyn Fn(&mut Request, Next) -> Response + Sync + Send>;
+
+/// Run `req` through `middlewares` in registration order, ending at `endpoint`.
+pub fn run_chain(middlewares: &[Middleware], req: &mut Request, endpoint: Next) -> Response {
+    match middlewares.split_first() {
+        Some((first, rest)) => first(req, &|req: &mut Request| run_chain(rest, req, endpoint)),
+        None => endpoint(req),
+    }
+}
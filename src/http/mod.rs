@@ -1,13 +1,46 @@
+mod chunked;
+mod compression;
+mod conditional;
+mod cookie;
 mod encoding;
 mod error;
+mod error_pages;
+mod extensions;
+mod extract;
+mod files;
+mod handler;
+mod into_response;
+mod middleware;
+mod multipart;
+mod range;
 mod request;
 mod response;
 mod router;
+mod safe_path;
+#[cfg(feature = "secure-cookies")]
+mod secure_cookie;
 mod server;
+mod session;
 // pub use error::HTTPError;
 // pub use request::Request;
+pub use compression::CompressionPolicy;
+pub use conditional::{evaluate, ETag, Validators};
+pub use cookie::{Cookie, CookieJar, SameSite};
 pub use encoding::*;
+pub use error_pages::{ErrorFormat, ErrorPages};
+pub use extensions::Extensions;
+pub use extract::*;
+pub use files::{file_response, mime_type, write_file, write_file_from};
+pub use files::{FileError, StaticFiles};
+pub use handler::Handler;
+pub use into_response::IntoResponse;
+pub use middleware::{Middleware, Next};
+pub use multipart::{Multipart, MultipartError, Part};
 pub use request::Method;
 pub use request::*;
 pub use response::*;
+pub use safe_path::{resolve_path, PathError};
+#[cfg(feature = "secure-cookies")]
+pub use secure_cookie::CookieKeys;
 pub use server::Server;
+pub use session::{FileStore, MemoryStore, Session, SessionRecord, SessionStore, Sessions};
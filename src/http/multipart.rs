@@ -0,0 +1,591 @@
+use std::{
+    collections::HashMap,
+    error, fmt,
+    io::{self, Read},
+};
+
+use super::{
+    into_response::IntoResponse,
+    request::{BodyReader, Request},
+    response::{Response, StatusCode},
+};
+
+/// Part headers larger than this are refused rather than buffered.
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+
+/// Why a `multipart/form-data` body could not be read.
+#[derive(Debug)]
+pub enum MultipartError {
+    /// The request isn't `multipart/form-data`.
+    NotMultipart,
+    /// The `Content-Type` has no usable `boundary` parameter.
+    MissingBoundary,
+    Malformed(&'static str),
+    PartTooLarge,
+    TooLarge,
+    Io(io::Error),
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::NotMultipart => write!(f, "body is not multipart/form-data"),
+            MultipartError::MissingBoundary => write!(f, "multipart boundary missing"),
+            MultipartError::Malformed(reason) => write!(f, "malformed multipart body: {}", reason),
+            MultipartError::PartTooLarge => write!(f, "multipart part too large"),
+            MultipartError::TooLarge => write!(f, "multipart body too large"),
+            MultipartError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for MultipartError {}
+
+impl From<io::Error> for MultipartError {
+    fn from(err: io::Error) -> Self {
+        // a `Part` read through `Read` carries its own error inside
+        match err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<MultipartError>())
+        {
+            Some(MultipartError::PartTooLarge) => MultipartError::PartTooLarge,
+            Some(MultipartError::TooLarge) => MultipartError::TooLarge,
+            Some(MultipartError::Malformed(reason)) => MultipartError::Malformed(reason),
+            _ => MultipartError::Io(err),
+        }
+    }
+}
+
+impl IntoResponse for MultipartError {
+    fn into_response(self) -> Response {
+        match self {
+            MultipartError::NotMultipart => StatusCode::UnsupportedMediaType,
+            MultipartError::MissingBoundary | MultipartError::Malformed(_) => {
+                StatusCode::BadRequest
+            }
+            MultipartError::PartTooLarge | MultipartError::TooLarge => StatusCode::PayloadTooLarge,
+            MultipartError::Io(err) => return err.into_response(),
+        }
+        .into_response()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum State {
+    /// Inside a part's body, or the preamble before the first one.
+    Body,
+    Headers,
+    End,
+}
+
+/// A streaming `multipart/form-data` parser.
+///
+/// Parts are yielded one at a time by `next_part`, and each part's body is
+/// read straight from the request body, so a file part can be copied to
+/// disk without holding it in memory. Moving to the next part skips
+/// whatever is left of the current one.
+pub struct Multipart {
+    reader: BodyReader,
+    /// `\r\n--boundary`; the first delimiter is found by pretending the body
+    /// starts with a line break.
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    state: State,
+    read_total: u64,
+    max_part_size: u64,
+    max_total_size: u64,
+}
+
+impl fmt::Debug for Multipart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Multipart")
+            .field("state", &self.state)
+            .field("read_total", &self.read_total)
+            .finish()
+    }
+}
+
+impl Multipart {
+    pub fn new(reader: BodyReader, boundary: &str) -> Self {
+        Self {
+            reader,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            buf: b"\r\n".to_vec(),
+            state: State::Body,
+            read_total: 0,
+            max_part_size: 64 * 1024 * 1024,
+            max_total_size: 256 * 1024 * 1024,
+        }
+    }
+
+    /// Parse the body of `req`, which must be `multipart/form-data`. The
+    /// body is left alone when it isn't.
+    pub fn from_request(req: &Request) -> Result<Self, MultipartError> {
+        let content_type = req
+            .get_headers()
+            .get("content-type")
+            .ok_or(MultipartError::NotMultipart)?;
+        let boundary = boundary(content_type)?;
+        Ok(Self::new(req.take_body_reader(), boundary))
+    }
+
+    /// Parse `reader` as a body of `content_type`, which must be
+    /// `multipart/form-data`.
+    pub fn with_content_type(
+        content_type: &str,
+        reader: BodyReader,
+    ) -> Result<Self, MultipartError> {
+        Ok(Self::new(reader, boundary(content_type)?))
+    }
+
+    /// Refuse any single part whose body is larger than `size` bytes.
+    pub fn set_max_part_size(&mut self, size: u64) {
+        self.max_part_size = size;
+    }
+
+    /// Refuse bodies larger than `size` bytes in all.
+    pub fn set_max_total_size(&mut self, size: u64) {
+        self.max_total_size = size;
+    }
+
+    /// The next part, or `None` once the closing delimiter is reached.
+    pub fn next_part(&mut self) -> Result<Option<Part<'_>>, MultipartError> {
+        let mut skipped = [0u8; 8 * 1024];
+        while self.state == State::Body {
+            self.read_body(&mut skipped)?;
+        }
+        if self.state == State::End {
+            return Ok(None);
+        }
+
+        let headers = loop {
+            if self.buf.starts_with(b"\r\n") {
+                self.buf.drain(..2);
+                break String::new();
+            }
+            if let Some(end) = find(&self.buf, b"\r\n\r\n") {
+                if end > MAX_HEADER_SIZE {
+                    return Err(MultipartError::Malformed("part headers too large"));
+                }
+                let headers = String::from_utf8_lossy(&self.buf[..end]).to_string();
+                self.buf.drain(..end + 4);
+                break headers;
+            }
+            if self.buf.len() > MAX_HEADER_SIZE {
+                return Err(MultipartError::Malformed("part headers too large"));
+            }
+            if !self.fill()? {
+                return Err(MultipartError::Malformed("body ended inside part headers"));
+            }
+        };
+        self.state = State::Body;
+
+        let headers = headers
+            .split("\r\n")
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+            .collect::<HashMap<_, _>>();
+        let disposition = headers
+            .get("content-disposition")
+            .map(|d| parse_params(d))
+            .unwrap_or_default();
+        Ok(Some(Part {
+            name: disposition.get("name").cloned(),
+            filename: disposition.get("filename").cloned(),
+            content_type: headers.get("content-type").cloned(),
+            headers,
+            size: 0,
+            multipart: self,
+        }))
+    }
+
+    /// Read more of the body into the buffer. `false` at its end.
+    fn fill(&mut self) -> Result<bool, MultipartError> {
+        let mut chunk = [0u8; 8 * 1024];
+        let n = self.reader.read(&mut chunk)?;
+        if n == 0 {
+            return Ok(false);
+        }
+        self.read_total += n as u64;
+        if self.read_total > self.max_total_size {
+            return Err(MultipartError::TooLarge);
+        }
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(true)
+    }
+
+    /// Read body bytes of the current part, up to the next delimiter. On
+    /// reaching it, moves on to the next part's headers or the end.
+    fn read_body(&mut self, out: &mut [u8]) -> Result<usize, MultipartError> {
+        if self.state != State::Body || out.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            // everything before a delimiter, or all but what could be the
+            // start of one
+            let available = match find(&self.buf, &self.delimiter) {
+                Some(0) => {
+                    self.buf.drain(..self.delimiter.len());
+                    self.end_delimiter()?;
+                    return Ok(0);
+                }
+                Some(at) => at,
+                None => self.buf.len().saturating_sub(self.delimiter.len() - 1),
+            };
+            if available > 0 {
+                let n = available.min(out.len());
+                out[..n].copy_from_slice(&self.buf[..n]);
+                self.buf.drain(..n);
+                return Ok(n);
+            }
+            if !self.fill()? {
+                return Err(MultipartError::Malformed("body ended inside a part"));
+            }
+        }
+    }
+
+    /// After a delimiter comes `--` for the last one, or optional
+    /// whitespace and a line break before the next part's headers.
+    fn end_delimiter(&mut self) -> Result<(), MultipartError> {
+        loop {
+            if self.buf.starts_with(b"--") {
+                self.state = State::End;
+                return Ok(());
+            }
+            let padding = self
+                .buf
+                .iter()
+                .take_while(|b| **b == b' ' || **b == b'\t')
+                .count();
+            if self.buf[padding..].starts_with(b"\r\n") {
+                self.buf.drain(..padding + 2);
+                self.state = State::Headers;
+                return Ok(());
+            }
+            if self.buf.len() > padding + 1 || !self.fill()? {
+                return Err(MultipartError::Malformed("bad delimiter line"));
+            }
+        }
+    }
+}
+
+/// One field or file of a `multipart/form-data` body. Its body is read
+/// through `Read`.
+pub struct Part<'a> {
+    name: Option<String>,
+    filename: Option<String>,
+    content_type: Option<String>,
+    headers: HashMap<String, String>,
+    size: u64,
+    multipart: &'a mut Multipart,
+}
+
+impl Part<'_> {
+    /// The form field name from `Content-Disposition`.
+    pub fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The client's name for an uploaded file. Untrusted: resolve it with
+    /// `resolve_path` before using it as a path.
+    pub fn get_filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn get_content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// The part's headers, keyed by lowercase name.
+    pub fn get_headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    /// Whether this part is a file upload rather than a plain field.
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    /// The rest of the body in memory.
+    pub fn read_all(&mut self) -> Result<Vec<u8>, MultipartError> {
+        let mut bytes = Vec::new();
+        self.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// The rest of the body as text, for plain fields.
+    pub fn text(&mut self) -> Result<String, MultipartError> {
+        String::from_utf8(self.read_all()?)
+            .map_err(|_| MultipartError::Malformed("field is not UTF-8"))
+    }
+}
+
+impl Read for Part<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let to_io = |err: MultipartError| match err {
+            MultipartError::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        };
+        let n = self.multipart.read_body(buf).map_err(to_io)?;
+        self.size += n as u64;
+        if self.size > self.multipart.max_part_size {
+            return Err(to_io(MultipartError::PartTooLarge));
+        }
+        Ok(n)
+    }
+}
+
+impl fmt::Debug for Part<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Part")
+            .field("name", &self.name)
+            .field("filename", &self.filename)
+            .field("content_type", &self.content_type)
+            .finish()
+    }
+}
+
+/// The `boundary` parameter of a `multipart/form-data` content type.
+fn boundary(content_type: &str) -> Result<&str, MultipartError> {
+    let mut params = content_type.split(';');
+    let media_type = params.next().unwrap_or("").trim();
+    if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+        return Err(MultipartError::NotMultipart);
+    }
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"'))
+        .filter(|boundary| (1..=70).contains(&boundary.len()))
+        .ok_or(MultipartError::MissingBoundary)
+}
+
+/// `name="value"` parameters after the first `;` of a header value.
+fn parse_params(value: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut rest = value.split_once(';').map(|(_, rest)| rest).unwrap_or("");
+    while let Some((name, after)) = rest.split_once('=') {
+        let name = name.trim().trim_start_matches(';').trim().to_lowercase();
+        let after = after.trim_start();
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        }
+                        c => value.push(c),
+                    }
+                }
+                (value, &quoted[end..])
+            }
+            None => {
+                let end = after.find(';').unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            }
+        };
+        params.insert(name, value);
+        rest = next;
+    }
+    params
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const BOUNDARY: &str = "XyZ-boundary";
+
+    /// Hands out one byte per read, so every delimiter arrives split.
+    struct OneByte(Cursor<Vec<u8>>);
+
+    impl Read for OneByte {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    fn multipart(body: &[u8], one_byte: bool) -> Multipart {
+        let length = body.len() as u64;
+        let reader = match one_byte {
+            true => BodyReader::new(OneByte(Cursor::new(body.to_vec())), length),
+            false => BodyReader::new(Cursor::new(body.to_vec()), length),
+        };
+        Multipart::new(reader, BOUNDARY)
+    }
+
+    /// A part's name, filename and body.
+    type Field = (Option<String>, Option<String>, Vec<u8>);
+
+    fn parts(multipart: &mut Multipart) -> Result<Vec<Field>, MultipartError> {
+        let mut parts = Vec::new();
+        while let Some(mut part) = multipart.next_part()? {
+            let name = part.get_name().map(str::to_string);
+            let filename = part.get_filename().map(str::to_string);
+            parts.push((name, filename, part.read_all()?));
+        }
+        Ok(parts)
+    }
+
+    fn form() -> Vec<u8> {
+        format!(
+            "--{b}\r\n\
+             Content-Disposition: form-data; name=\"title\"\r\n\
+             \r\n\
+             hello\r\n\
+             --{b}  \r\n\
+             Content-Disposition: form-data; name=\"upload\"; filename=\"a \\\"b\\\".txt\"\r\n\
+             Content-Type: text/plain\r\n\
+             \r\n\
+             line\r\n--XyZ-bound\r\n-- not yet\r\n\
+             --{b}--\r\n\
+             epilogue",
+            b = BOUNDARY
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn reads_fields_and_files() {
+        for one_byte in [false, true] {
+            let parts = parts(&mut multipart(&form(), one_byte)).unwrap();
+            assert_eq!(
+                parts,
+                vec![
+                    (Some("title".to_string()), None, b"hello".to_vec()),
+                    (
+                        Some("upload".to_string()),
+                        Some("a \"b\".txt".to_string()),
+                        b"line\r\n--XyZ-bound\r\n-- not yet".to_vec()
+                    ),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn skips_the_preamble() {
+        let body = format!(
+            "This is a preamble.\r\n--{b}\r\n\r\nvalue\r\n--{b}--",
+            b = BOUNDARY
+        );
+        for one_byte in [false, true] {
+            let parts = parts(&mut multipart(body.as_bytes(), one_byte)).unwrap();
+            assert_eq!(parts, vec![(None, None, b"value".to_vec())]);
+        }
+    }
+
+    #[test]
+    fn reads_an_empty_form() {
+        let body = format!("--{}--", BOUNDARY);
+        for one_byte in [false, true] {
+            let mut multipart = multipart(body.as_bytes(), one_byte);
+            assert!(multipart.next_part().unwrap().is_none());
+            assert!(multipart.next_part().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn skips_unread_parts() {
+        let mut multipart = multipart(&form(), true);
+        assert!(multipart.next_part().unwrap().is_some());
+        let mut part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(part.get_content_type(), Some("text/plain"));
+        assert_eq!(part.text().unwrap(), "line\r\n--XyZ-bound\r\n-- not yet");
+        assert!(multipart.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_a_bad_delimiter_line() {
+        let body = format!("--{b}junk\r\n\r\nvalue\r\n--{b}--", b = BOUNDARY);
+        for one_byte in [false, true] {
+            let err = parts(&mut multipart(body.as_bytes(), one_byte)).unwrap_err();
+            assert!(matches!(
+                err,
+                MultipartError::Malformed("bad delimiter line")
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_bodies() {
+        let body = format!("--{}\r\n\r\nvalue", BOUNDARY);
+        let err = parts(&mut multipart(body.as_bytes(), false)).unwrap_err();
+        assert!(matches!(
+            err,
+            MultipartError::Malformed("body ended inside a part")
+        ));
+        let body = format!("--{}\r\nContent-Type: text/plain", BOUNDARY);
+        let err = parts(&mut multipart(body.as_bytes(), false)).unwrap_err();
+        assert!(matches!(
+            err,
+            MultipartError::Malformed("body ended inside part headers")
+        ));
+    }
+
+    #[test]
+    fn refuses_oversized_headers() {
+        let body = format!(
+            "--{b}\r\nX-Padding: {pad}\r\n\r\nvalue\r\n--{b}--",
+            b = BOUNDARY,
+            pad = "a".repeat(MAX_HEADER_SIZE)
+        );
+        for one_byte in [false, true] {
+            let err = parts(&mut multipart(body.as_bytes(), one_byte)).unwrap_err();
+            assert!(matches!(
+                err,
+                MultipartError::Malformed("part headers too large")
+            ));
+        }
+    }
+
+    #[test]
+    fn refuses_oversized_parts() {
+        let mut multipart = multipart(&form(), false);
+        multipart.set_max_part_size(5);
+        let mut part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(part.read_all().unwrap(), b"hello");
+        let mut part = multipart.next_part().unwrap().unwrap();
+        assert!(matches!(part.read_all(), Err(MultipartError::PartTooLarge)));
+    }
+
+    #[test]
+    fn refuses_oversized_bodies() {
+        let mut multipart = multipart(&form(), false);
+        multipart.set_max_total_size(64);
+        assert!(matches!(
+            parts(&mut multipart),
+            Err(MultipartError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn finds_the_boundary() {
+        assert_eq!(
+            boundary("multipart/form-data; charset=utf-8; boundary=\"a b\"").unwrap(),
+            "a b"
+        );
+        assert!(matches!(
+            boundary("application/json"),
+            Err(MultipartError::NotMultipart)
+        ));
+        assert!(matches!(
+            boundary("multipart/form-data"),
+            Err(MultipartError::MissingBoundary)
+        ));
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    fs,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Requests asking for more ranges than this are served in full; answering
+/// them would cost more than sending the whole file.
+const MAX_RANGES: usize = 32;
+
+/// An inclusive byte range, resolved against the length of the
+/// representation it selects from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// The `Content-Range` value for this range of a `total` byte body.
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+/// None of the requested ranges overlap the representation.
+#[derive(Debug)]
+pub struct RangeNotSatisfiable;
+
+/// Parse a `Range` header for a representation of `length` bytes.
+///
+/// Returns `Ok(None)` when the header should be ignored and the full body
+/// sent: it is malformed, names a unit other than `bytes`, or asks for too
+/// many ranges. Ranges that can't be satisfied are dropped; if that leaves
+/// none the request is unsatisfiable. Overlapping and adjacent ranges are
+/// merged.
+pub fn parse_range(
+    header: &str,
+    length: u64,
+) -> Result<Option<Vec<ByteRange>>, RangeNotSatisfiable> {
+    let (unit, specs) = match header.split_once('=') {
+        Some(parts) => parts,
+        None => return Ok(None),
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return Ok(None);
+    }
+
+    let specs = specs
+        .split(',')
+        .map(|spec| spec.trim())
+        .filter(|spec| !spec.is_empty())
+        .collect::<Vec<_>>();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return Ok(None);
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let (first, last) = match spec.split_once('-') {
+            Some(parts) => parts,
+            None => return Ok(None),
+        };
+        let range = match (first.parse::<u64>(), last) {
+            // `-n`: the final n bytes
+            (Err(_), last) if first.is_empty() => match last.parse::<u64>() {
+                Ok(0) => None,
+                Ok(suffix) if length > 0 => Some(ByteRange {
+                    start: length.saturating_sub(suffix),
+                    end: length - 1,
+                }),
+                Ok(_) => None,
+                Err(_) => return Ok(None),
+            },
+            (Err(_), _) => return Ok(None),
+            // `n-`: from byte n to the end
+            (Ok(start), "") if start < length => Some(ByteRange {
+                start,
+                end: length - 1,
+            }),
+            (Ok(_), "") => None,
+            (Ok(start), last) => match last.parse::<u64>() {
+                Ok(end) if end < start => return Ok(None),
+                // checked first, so `length - 1` can't underflow on an empty body
+                Ok(end) if start < length => Some(ByteRange {
+                    start,
+                    end: end.min(length - 1),
+                }),
+                Ok(_) => None,
+                Err(_) => return Ok(None),
+            },
+        };
+        ranges.extend(range);
+    }
+
+    if ranges.is_empty() {
+        return Err(RangeNotSatisfiable);
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(prev) if range.start <= prev.end + 1 => prev.end = prev.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    Ok(Some(merged))
+}
+
+/// Open `path` and read only the bytes within `range`.
+pub fn range_reader(path: &Path, range: ByteRange) -> io::Result<io::Take<fs::File>> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(range.start))?;
+    Ok(file.take(range.len()))
+}
+
+/// A `multipart/byteranges` body, one part per range of a file.
+pub struct ByteRanges {
+    boundary: String,
+    parts: Vec<(String, ByteRange)>,
+    closing: String,
+}
+
+impl ByteRanges {
+    pub fn new(ranges: &[ByteRange], content_type: &str, total: u64) -> Self {
+        let boundary = new_boundary();
+        let parts = ranges
+            .iter()
+            .map(|range| {
+                let head = format!(
+                    "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                    boundary,
+                    content_type,
+                    range.content_range(total)
+                );
+                (head, *range)
+            })
+            .collect();
+        let closing = format!("\r\n--{}--\r\n", boundary);
+        Self {
+            boundary,
+            parts,
+            closing,
+        }
+    }
+
+    /// The `Content-Type` for the whole body.
+    pub fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    /// Size of the body `reader` produces, part headers included.
+    pub fn content_length(&self) -> u64 {
+        let parts = self
+            .parts
+            .iter()
+            .map(|(head, range)| head.len() as u64 + range.len())
+            .sum::<u64>();
+        parts + self.closing.len() as u64
+    }
+
+    /// Stream the body, reading each range from `path` as it is reached.
+    pub fn reader(self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let mut body: Box<dyn Read + Send> = Box::new(io::empty());
+        for (head, range) in self.parts {
+            let part = range_reader(path, range)?;
+            body = Box::new(body.chain(Cursor::new(head)).chain(part));
+        }
+        Ok(Box::new(body.chain(Cursor::new(self.closing))))
+    }
+}
+
+/// A boundary that is vanishingly unlikely to appear in the file itself.
+fn new_boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("byteranges-{:x}{:x}", nanos, std::process::id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    #[test]
+    fn parses_a_single_range() {
+        let ranges = parse_range("bytes=0-4", 10).unwrap();
+        assert_eq!(ranges, Some(vec![range(0, 4)]));
+    }
+
+    #[test]
+    fn clamps_the_end_to_the_body() {
+        let ranges = parse_range("bytes=5-100", 10).unwrap();
+        assert_eq!(ranges, Some(vec![range(5, 9)]));
+        let ranges = parse_range("bytes=5-", 10).unwrap();
+        assert_eq!(ranges, Some(vec![range(5, 9)]));
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(
+            parse_range("bytes=-3", 10).unwrap(),
+            Some(vec![range(7, 9)])
+        );
+        // a suffix longer than the body selects all of it
+        assert_eq!(
+            parse_range("bytes=-50", 10).unwrap(),
+            Some(vec![range(0, 9)])
+        );
+        assert!(parse_range("bytes=-0", 10).is_err());
+    }
+
+    #[test]
+    fn nothing_is_satisfiable_in_an_empty_body() {
+        assert!(parse_range("bytes=0-5", 0).is_err());
+        assert!(parse_range("bytes=0-", 0).is_err());
+        assert!(parse_range("bytes=-5", 0).is_err());
+    }
+
+    #[test]
+    fn drops_unsatisfiable_ranges() {
+        let ranges = parse_range("bytes=20-30, 0-1", 10).unwrap();
+        assert_eq!(ranges, Some(vec![range(0, 1)]));
+        assert!(parse_range("bytes=10-20", 10).is_err());
+    }
+
+    #[test]
+    fn merges_overlapping_and_adjacent_ranges() {
+        let ranges = parse_range("bytes=5-7, 0-2, 3-4, 6-9", 10).unwrap();
+        assert_eq!(ranges, Some(vec![range(0, 9)]));
+        let ranges = parse_range("bytes=0-1, 4-5", 10).unwrap();
+        assert_eq!(ranges, Some(vec![range(0, 1), range(4, 5)]));
+    }
+
+    #[test]
+    fn ignores_malformed_headers() {
+        for header in [
+            "bytes",
+            "items=0-1",
+            "bytes=",
+            "bytes=5",
+            "bytes=a-b",
+            "bytes=1-a",
+            "bytes=5-2",
+            "bytes=-",
+            "bytes=--1",
+        ] {
+            assert_eq!(parse_range(header, 10).unwrap(), None, "{}", header);
+        }
+    }
+
+    #[test]
+    fn ignores_too_many_ranges() {
+        let header = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        assert_eq!(parse_range(&header, 10).unwrap(), None);
+    }
+}
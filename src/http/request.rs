@@ -1,24 +1,53 @@
-use super::error::HTTPError;
+use super::{
+    cookie::CookieJar,
+    error::HTTPError,
+    extensions::Extensions,
+    multipart::{Multipart, MultipartError},
+};
 use std::fmt;
-use std::{collections::HashMap, result::Result, str::FromStr};
+use std::io::{self, Cursor, Read, Write};
+use std::{
+    collections::HashMap,
+    result::Result,
+    str::FromStr,
+    sync::{Arc, Mutex, PoisonError},
+};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Method {
     GET,
     POST,
+    PUT,
+    DELETE,
     UNKNOWN,
 }
 
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Method::GET => write!(f, "GET"),
+            Method::POST => write!(f, "POST"),
+            Method::PUT => write!(f, "PUT"),
+            Method::DELETE => write!(f, "DELETE"),
+            Method::UNKNOWN => write!(f, "UNKNOWN"),
+        }
+    }
+}
+
 impl From<&str> for Method {
     fn from(s: &str) -> Self {
         match s {
             "GET" => Method::GET,
             "POST" => Method::POST,
+            "PUT" => Method::PUT,
+            "DELETE" => Method::DELETE,
             _ => Method::UNKNOWN,
         }
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
 pub enum HTTPVersion {
     HTTP1_1,
@@ -72,14 +101,99 @@ impl fmt::Display for RequestBody {
     }
 }
 
+/// A request body read straight from the connection as the handler
+/// consumes it, rather than collected up front.
+///
+/// Exactly `Content-Length` bytes are read; a connection that closes before
+/// they have all arrived is an `UnexpectedEof` error, so a handler never
+/// mistakes a cut-off upload for a complete one.
+pub struct BodyReader {
+    inner: Box<dyn Read + Send>,
+    /// `None` for a body decoded as it is read, whose length isn't known.
+    remaining: Option<u64>,
+    continue_writer: Option<Box<dyn Write + Send>>,
+}
+
+impl BodyReader {
+    pub fn new<R: Read + Send + 'static>(inner: R, length: u64) -> Self {
+        Self {
+            inner: Box::new(inner),
+            remaining: Some(length),
+            continue_writer: None,
+        }
+    }
+
+    /// A body of unknown length that ends where `inner` does, such as one
+    /// decoded from its `Content-Encoding` while it is read.
+    pub fn until_eof<R: Read + Send + 'static>(inner: R) -> Self {
+        Self {
+            inner: Box::new(inner),
+            remaining: None,
+            continue_writer: None,
+        }
+    }
+
+    /// The client sent `Expect: 100-continue` and waits for a go-ahead
+    /// before sending the body: write `100 Continue` to `writer` on the
+    /// first read. A request answered without reading its body never gets
+    /// one, so the client learns of the final status without uploading.
+    pub fn set_continue_writer<W: Write + Send + 'static>(&mut self, writer: W) {
+        self.continue_writer = Some(Box::new(writer));
+    }
+
+    /// Bytes still to be read, if the length is known.
+    pub fn remaining(&self) -> Option<u64> {
+        self.remaining
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == Some(0) || buf.is_empty() {
+            return Ok(0);
+        }
+        if let Some(mut writer) = self.continue_writer.take() {
+            writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            writer.flush()?;
+        }
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => return self.inner.read(buf),
+        };
+        let max = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("connection closed with {} body bytes missing", remaining),
+            ));
+        }
+        self.remaining = Some(remaining - n as u64);
+        Ok(n)
+    }
+}
+
+impl fmt::Debug for BodyReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BodyReader")
+            .field("remaining", &self.remaining)
+            .finish()
+    }
+}
+
 #[derive(Debug)]
 pub struct Request {
     method: Method,
     target: RequestTarget,
+    // parsed from the request line, but nothing varies by version yet
+    #[allow(dead_code)]
     version: HTTPVersion,
     headers: HashMap<String, String>,
     body: Option<RequestBody>,
+    body_reader: Mutex<Option<BodyReader>>,
     params: HashMap<String, String>,
+    state: Arc<Extensions>,
+    extensions: Extensions,
 }
 
 impl Request {
@@ -89,6 +203,22 @@ impl Request {
         }
     }
 
+    /// The target without its query string.
+    pub fn get_path(&self) -> String {
+        let target = self.get_target();
+        match target.split_once('?') {
+            Some((path, _)) => path.to_string(),
+            None => target,
+        }
+    }
+
+    /// The raw query string, without the leading `?`.
+    pub fn get_query(&self) -> Option<String> {
+        self.get_target()
+            .split_once('?')
+            .map(|(_, query)| query.to_string())
+    }
+
     pub fn get_method(&self) -> Method {
         self.method
     }
@@ -101,6 +231,77 @@ impl Request {
         self.body = Some(b);
     }
 
+    /// Leave the body on the connection, to be read through
+    /// `take_body_reader`.
+    pub fn set_body_reader(&mut self, reader: BodyReader) {
+        self.body = None;
+        *self
+            .body_reader
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner) = Some(reader);
+    }
+
+    /// Whether the body is still unread on the connection.
+    pub fn has_body_reader(&self) -> bool {
+        self.body_reader
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some()
+    }
+
+    /// The body as a stream. The first call takes the reader over the
+    /// connection when the body hasn't been read yet; otherwise the reader
+    /// yields the buffered body.
+    pub fn take_body_reader(&self) -> BodyReader {
+        let reader = self
+            .body_reader
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        match (reader, &self.body) {
+            (Some(reader), _) => reader,
+            (None, Some(RequestBody::String(bytes))) => {
+                BodyReader::new(Cursor::new(bytes.clone()), bytes.len() as u64)
+            }
+            (None, None) => BodyReader::new(io::empty(), 0),
+        }
+    }
+
+    /// The cookies sent in the `Cookie` header.
+    pub fn get_cookies(&self) -> CookieJar {
+        self.headers
+            .get("cookie")
+            .map(|header| CookieJar::parse(header))
+            .unwrap_or_default()
+    }
+
+    /// The body parsed as `multipart/form-data`, streamed part by part.
+    pub fn take_multipart(&self) -> Result<Multipart, MultipartError> {
+        Multipart::from_request(self)
+    }
+
+    /// Read the rest of a streamed body into memory, so `get_body` sees it.
+    /// Returns `false`, leaving the body unset, when it is longer than
+    /// `limit` bytes.
+    pub fn buffer_body(&mut self, limit: u64) -> io::Result<bool> {
+        let reader = self
+            .body_reader
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(reader) = reader {
+            let mut bytes = Vec::new();
+            reader
+                .take(limit.saturating_add(1))
+                .read_to_end(&mut bytes)?;
+            if bytes.len() as u64 > limit {
+                return Ok(false);
+            }
+            self.body = Some(RequestBody::String(bytes));
+        }
+        Ok(true)
+    }
+
     pub fn set_params(&mut self, params: HashMap<String, String>) {
         self.params = params;
     }
@@ -109,6 +310,32 @@ impl Request {
         self.params.clone()
     }
 
+    /// Shared application state registered with `Server::add_state`.
+    pub fn get_state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.state.get::<T>()
+    }
+
+    pub fn set_state(&mut self, state: Arc<Extensions>) {
+        self.state = state;
+    }
+
+    /// Per-request data attached by middleware for downstream handlers.
+    pub fn get_extension<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions.get::<T>()
+    }
+
+    pub fn insert_extension<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.extensions.insert(value)
+    }
+
+    pub fn get_extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn get_extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
     fn separate_body_from_request(bytes: &[u8]) -> (Vec<u8>, Vec<u8>) {
         // Find the position of the double CRLF that separates the headers from the body
         let mut headers_end = None;
@@ -135,21 +362,30 @@ impl Request {
     pub fn get_headers(&self) -> &HashMap<String, String> {
         &self.headers
     }
+
+    /// Header names are stored lowercase, as they are when parsed.
+    pub fn set_header(&mut self, key: &str, value: &str) {
+        self.headers.insert(key.to_lowercase(), value.to_string());
+    }
+
+    pub fn remove_header(&mut self, key: &str) -> Option<String> {
+        self.headers.remove(&key.to_lowercase())
+    }
 }
 
-impl From<Vec<u8>> for Request {
-    fn from(bytes: Vec<u8>) -> Self {
+impl TryFrom<Vec<u8>> for Request {
+    type Error = HTTPError;
+    fn try_from(bytes: Vec<u8>) -> Result<Self, HTTPError> {
         // seprate body because it may contain not utf8 elements
         // TODO: parse body based on content-type
         let (up_to_header, body) = Request::separate_body_from_request(&bytes[..]);
 
         let mut req = String::from_utf8(up_to_header)
-            .unwrap()
-            .parse::<Request>()
-            .expect("Can not parse header");
+            .map_err(|_| HTTPError::Custom)?
+            .parse::<Request>()?;
 
-        req.body = Some(RequestBody::String(Vec::from(body)));
-        req
+        req.body = Some(RequestBody::String(body));
+        Ok(req)
     }
 }
 
@@ -161,7 +397,7 @@ impl FromStr for Request {
 
             // parse http method
             let method_target_version: Vec<&str> = first_line.split(" ").collect();
-            let method = match method_target_version.get(0) {
+            let method = match method_target_version.first() {
                 Some(method) => Method::from(*method),
                 None => {
                     return Err(HTTPError::Custom);
@@ -190,13 +426,10 @@ impl FromStr for Request {
                 .split("\r\n")
                 .take_while(|header_line| header_line != &"")
                 .for_each(|header_line| {
-                    let split: Vec<&str> = header_line.split(":").collect();
-                    if split.len() < 2 {
-                        return;
+                    // values may contain colons themselves, as HTTP-dates do
+                    if let Some((key, value)) = header_line.split_once(':') {
+                        headers.insert(key.trim().to_lowercase(), value.trim().to_string());
                     }
-                    let key = split.first().unwrap().trim().to_lowercase().to_string();
-                    let value = split.get(1).unwrap().trim().to_string();
-                    headers.insert(key, value);
                 });
 
             Ok(Self {
@@ -205,7 +438,10 @@ impl FromStr for Request {
                 version,
                 headers,
                 body: None,
+                body_reader: Mutex::new(None),
                 params: HashMap::new(),
+                state: Arc::new(Extensions::new()),
+                extensions: Extensions::new(),
             })
         } else {
             Err(HTTPError::Custom)
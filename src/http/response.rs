@@ -1,23 +1,124 @@
 use std::collections::HashMap;
 use std::fmt;
+use std::io::Read;
 
-use super::request::{HTTPVersion, RequestBody};
+use super::{
+    cookie::Cookie,
+    request::{HTTPVersion, RequestBody},
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum StatusCode {
     Ok,
     Created,
+    NoContent,
+    PartialContent,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Forbidden,
     NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    Conflict,
+    PreconditionFailed,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    ExpectationFailed,
+    InternalServerError,
+    NotImplemented,
+    InsufficientStorage,
 }
 
-impl fmt::Display for StatusCode {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl StatusCode {
+    pub fn code(&self) -> u16 {
         match self {
-            StatusCode::Ok => write!(f, "200 OK"),
-            StatusCode::NotFound => write!(f, "404 Not Found"),
-            StatusCode::Created => write!(f, "201 Created"),
+            StatusCode::Ok => 200,
+            StatusCode::Created => 201,
+            StatusCode::NoContent => 204,
+            StatusCode::PartialContent => 206,
+            StatusCode::MovedPermanently => 301,
+            StatusCode::Found => 302,
+            StatusCode::SeeOther => 303,
+            StatusCode::NotModified => 304,
+            StatusCode::TemporaryRedirect => 307,
+            StatusCode::PermanentRedirect => 308,
+            StatusCode::BadRequest => 400,
+            StatusCode::Forbidden => 403,
+            StatusCode::NotFound => 404,
+            StatusCode::MethodNotAllowed => 405,
+            StatusCode::NotAcceptable => 406,
+            StatusCode::Conflict => 409,
+            StatusCode::PreconditionFailed => 412,
+            StatusCode::PayloadTooLarge => 413,
+            StatusCode::UnsupportedMediaType => 415,
+            StatusCode::RangeNotSatisfiable => 416,
+            StatusCode::ExpectationFailed => 417,
+            StatusCode::InternalServerError => 500,
+            StatusCode::NotImplemented => 501,
+            StatusCode::InsufficientStorage => 507,
         }
     }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            StatusCode::Ok => "OK",
+            StatusCode::Created => "Created",
+            StatusCode::NoContent => "No Content",
+            StatusCode::PartialContent => "Partial Content",
+            StatusCode::MovedPermanently => "Moved Permanently",
+            StatusCode::Found => "Found",
+            StatusCode::SeeOther => "See Other",
+            StatusCode::NotModified => "Not Modified",
+            StatusCode::TemporaryRedirect => "Temporary Redirect",
+            StatusCode::PermanentRedirect => "Permanent Redirect",
+            StatusCode::BadRequest => "Bad Request",
+            StatusCode::Forbidden => "Forbidden",
+            StatusCode::NotFound => "Not Found",
+            StatusCode::MethodNotAllowed => "Method Not Allowed",
+            StatusCode::NotAcceptable => "Not Acceptable",
+            StatusCode::Conflict => "Conflict",
+            StatusCode::PreconditionFailed => "Precondition Failed",
+            StatusCode::PayloadTooLarge => "Payload Too Large",
+            StatusCode::UnsupportedMediaType => "Unsupported Media Type",
+            StatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+            StatusCode::ExpectationFailed => "Expectation Failed",
+            StatusCode::InternalServerError => "Internal Server Error",
+            StatusCode::NotImplemented => "Not Implemented",
+            StatusCode::InsufficientStorage => "Insufficient Storage",
+        }
+    }
+
+    /// Whether this is a 4xx or 5xx status.
+    pub fn is_error(&self) -> bool {
+        self.code() >= 400
+    }
+
+    /// Whether a response with this status may carry a body; `204` and
+    /// `304` never do.
+    pub fn allows_body(&self) -> bool {
+        !matches!(self, StatusCode::NoContent | StatusCode::NotModified)
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason())
+    }
+}
+
+/// A response body produced incrementally while it is written to the client.
+pub struct BodyStream(Box<dyn Read + Send>);
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BodyStream")
+    }
 }
 
 #[derive(Debug)]
@@ -26,17 +127,23 @@ pub struct Response {
     headers: HashMap<String, String>,
     status_code: StatusCode,
     body: Option<RequestBody>,
+    stream: Option<BodyStream>,
+    cookies: Vec<Cookie>,
 }
 
 impl fmt::Display for Response {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // Write the status line
-        write!(f, "{} {}\r\n", self.version, self.status_code.to_string())?;
+        write!(f, "{} {}\r\n", self.version, self.status_code)?;
 
         // Write the headers
         for (key, value) in &self.headers {
             write!(f, "{}: {}\r\n", key, value)?;
         }
+        // one header per cookie; they can't be folded into a list
+        for cookie in &self.cookies {
+            write!(f, "Set-Cookie: {}\r\n", cookie)?;
+        }
 
         // Write a blank line to separate headers from the body
         write!(f, "\r\n")?;
@@ -58,15 +165,78 @@ impl Response {
             headers,
             status_code,
             body: None,
+            stream: None,
+            cookies: Vec::new(),
         }
     }
 
+    /// Start building a `200 OK` response. Framing headers are worked out
+    /// by the server when the response is sent.
+    pub fn builder() -> ResponseBuilder {
+        ResponseBuilder {
+            res: Response::new(HTTPVersion::HTTP1_1, HashMap::new(), StatusCode::Ok),
+        }
+    }
+
+    /// A `200 OK` plain text response.
+    pub fn text(body: impl Into<String>) -> Self {
+        Self::builder()
+            .header("Content-Type", "text/plain")
+            .body(body.into())
+    }
+
+    /// A `200 OK` HTML response.
+    pub fn html(body: impl Into<String>) -> Self {
+        Self::builder()
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(body.into())
+    }
+
+    /// A `200 OK` response carrying arbitrary bytes.
+    pub fn bytes(body: impl Into<Vec<u8>>) -> Self {
+        Self::builder()
+            .header("Content-Type", "application/octet-stream")
+            .body(body)
+    }
+
+    /// A `303 See Other` to `location`, which the client follows with a
+    /// `GET`. Other redirects can be built with the status they need.
+    pub fn redirect(location: &str) -> Self {
+        Self::builder()
+            .status(StatusCode::SeeOther)
+            .header("Location", location)
+            .build()
+    }
+
+    pub fn no_content() -> Self {
+        Self::builder().status(StatusCode::NoContent).build()
+    }
+
     pub fn get_body(&self) -> Option<RequestBody> {
         self.body.clone()
     }
 
     pub fn set_body(&mut self, body: RequestBody) {
         self.body = Some(body);
+        self.stream = None;
+    }
+
+    /// Send the body by reading `reader` to the end instead of from memory.
+    ///
+    /// A `Content-Length` header set by the handler is trusted as the
+    /// stream's length and at most that many bytes are sent; without one the
+    /// body goes out with `Transfer-Encoding: chunked`.
+    pub fn set_stream<R: Read + Send + 'static>(&mut self, reader: R) {
+        self.stream = Some(BodyStream(Box::new(reader)));
+        self.body = None;
+    }
+
+    pub fn take_stream(&mut self) -> Option<Box<dyn Read + Send>> {
+        self.stream.take().map(|BodyStream(reader)| reader)
+    }
+
+    pub fn has_stream(&self) -> bool {
+        self.stream.is_some()
     }
 
     pub fn get_headers(&self) -> HashMap<String, String> {
@@ -76,4 +246,100 @@ impl Response {
     pub fn set_headers(&mut self, h: HashMap<String, String>) {
         self.headers = h;
     }
+
+    /// Look up a header regardless of the case it was set with.
+    pub fn get_header(&self, key: &str) -> Option<&String> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+
+    /// Set a header, replacing any existing value under a differently cased
+    /// name.
+    pub fn set_header(&mut self, key: &str, value: &str) {
+        self.remove_header(key);
+        self.headers.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn remove_header(&mut self, key: &str) -> Option<String> {
+        let existing = self
+            .headers
+            .keys()
+            .find(|k| k.eq_ignore_ascii_case(key))
+            .cloned();
+        existing.and_then(|k| self.headers.remove(&k))
+    }
+
+    /// Send `cookie` in its own `Set-Cookie` header, replacing a cookie
+    /// added earlier with the same name, path and domain.
+    pub fn add_cookie(&mut self, cookie: Cookie) {
+        self.cookies.retain(|c| {
+            c.get_name() != cookie.get_name()
+                || c.get_path() != cookie.get_path()
+                || c.get_domain() != cookie.get_domain()
+        });
+        self.cookies.push(cookie);
+    }
+
+    pub fn get_cookies(&self) -> &[Cookie] {
+        &self.cookies
+    }
+
+    pub fn get_status_code(&self) -> StatusCode {
+        self.status_code
+    }
+
+    pub fn set_status_code(&mut self, status_code: StatusCode) {
+        self.status_code = status_code;
+    }
+}
+
+/// Assembles a `Response`, started with `Response::builder`:
+///
+/// ```ignore
+/// Response::builder()
+///     .status(StatusCode::Created)
+///     .header("Location", "/files/report.txt")
+///     .body("saved")
+/// ```
+#[derive(Debug)]
+pub struct ResponseBuilder {
+    res: Response,
+}
+
+impl ResponseBuilder {
+    pub fn status(mut self, status_code: StatusCode) -> Self {
+        self.res.set_status_code(status_code);
+        self
+    }
+
+    /// Set a header, replacing any earlier value for it.
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.res.set_header(key, value);
+        self
+    }
+
+    pub fn cookie(mut self, cookie: Cookie) -> Self {
+        self.res.add_cookie(cookie);
+        self
+    }
+
+    /// Finish with `body` held in memory.
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.res.set_body(RequestBody::String(body.into()));
+        self.res
+    }
+
+    /// Finish with a body read from `reader` as it is sent; see
+    /// `Response::set_stream`.
+    pub fn stream<R: Read + Send + 'static>(mut self, reader: R) -> Response {
+        self.res.set_stream(reader);
+        self.res
+    }
+
+    /// Finish without a body.
+    pub fn build(self) -> Response {
+        self.res
+    }
 }
@@ -1,16 +1,23 @@
-use std::collections::HashMap;
-
-use itertools::Itertools;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use super::{
+    error_pages::ErrorPages,
+    files::StaticFiles,
+    handler::Handler,
+    middleware::{Middleware, Next},
     request::{Method, Request},
     response::Response,
 };
 
-type Handler = Box<dyn Fn(&Request) -> Response + Sync + Send>;
+type BoxedHandler = Box<dyn Fn(&Request) -> Response + Sync + Send>;
 
 struct Node {
-    handlers: HashMap<Method, Handler>,
+    handlers: HashMap<Method, BoxedHandler>,
+    /// Methods whose handler reads the body from the connection itself.
+    streaming: HashSet<Method>,
     children: HashMap<String, Node>,
     param: Option<String>,
 }
@@ -29,26 +36,83 @@ impl Node {
     pub fn new() -> Self {
         Self {
             handlers: HashMap::new(),
+            streaming: HashSet::new(),
             children: HashMap::new(),
             param: None,
         }
     }
 }
 
-#[derive(Debug)]
 pub struct Router {
     root: Node,
+    middlewares: Vec<Middleware>,
+    fallback: Option<BoxedHandler>,
+    error_pages: ErrorPages,
+}
+
+impl std::fmt::Debug for Router {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Router")
+            .field("root", &self.root)
+            .field("middlewares", &self.middlewares.len())
+            .field("fallback", &self.fallback.is_some())
+            .field("error_pages", &self.error_pages)
+            .finish()
+    }
 }
 
 impl Router {
     pub fn new() -> Self {
-        Self { root: Node::new() }
+        Self {
+            root: Node::new(),
+            middlewares: Vec::new(),
+            fallback: None,
+            error_pages: ErrorPages::new(),
+        }
     }
 
-    pub fn add_route<F>(&mut self, method: Method, origin_form: &str, f: F)
+    pub fn add_middleware<F>(&mut self, f: F)
     where
-        F: Fn(&Request) -> Response + 'static + Send + Sync,
+        F: Fn(&mut Request, Next) -> Response + 'static + Send + Sync,
     {
+        self.middlewares.push(Box::new(f));
+    }
+
+    pub fn get_middlewares(&self) -> &[Middleware] {
+        &self.middlewares
+    }
+
+    pub fn add_route<H, Args>(&mut self, method: Method, origin_form: &str, h: H)
+    where
+        H: Handler<Args>,
+    {
+        let node = self.node_mut(origin_form);
+        node.streaming.remove(&method);
+        node.handlers
+            .insert(method, Box::new(move |req| h.call(req)));
+    }
+
+    /// Like `add_route`, but the body is left unread for the handler to
+    /// consume with `Request::take_body_reader`, so uploads of any size use
+    /// bounded memory. A body sent with a `Content-Encoding` is decoded as
+    /// it is read, and is held to the server's decoded body size limit.
+    pub fn add_streaming_route<H, Args>(&mut self, method: Method, origin_form: &str, h: H)
+    where
+        H: Handler<Args>,
+    {
+        let node = self.node_mut(origin_form);
+        node.streaming.insert(method);
+        node.handlers
+            .insert(method, Box::new(move |req| h.call(req)));
+    }
+
+    /// Whether the route matching `origin_form` for `method` streams its body.
+    pub fn is_streaming(&self, method: Method, origin_form: &str) -> bool {
+        let (_, node) = self.find_node(origin_form);
+        node.is_some_and(|node| node.streaming.contains(&method))
+    }
+
+    fn node_mut(&mut self, origin_form: &str) -> &mut Node {
         let segments = origin_form.split('/').filter(|s| !s.is_empty());
         let mut current_node = &mut self.root;
         for segment in segments {
@@ -62,15 +126,90 @@ impl Router {
             };
             current_node = current_node.children.entry(key).or_insert_with(Node::new);
         }
+        current_node
+    }
+
+    /// Serve `files` for GET requests to `prefix` and every path below it,
+    /// and PUT and DELETE requests too if they are writable.
+    pub fn mount(&mut self, prefix: &str, files: StaticFiles) {
+        let files = Arc::new(files);
+        let prefix = prefix.trim_end_matches('/');
+
+        if files.is_writable() {
+            let put_files = Arc::clone(&files);
+            self.add_streaming_route(
+                Method::PUT,
+                &format!("{}/{{path}}", prefix),
+                move |req: &Request| {
+                    let rel_path = req.get_params().into_values().next().unwrap_or_default();
+                    put_files.put(&rel_path, req)
+                },
+            );
+            let delete_files = Arc::clone(&files);
+            self.add_route(
+                Method::DELETE,
+                &format!("{}/{{path}}", prefix),
+                move |req: &Request| {
+                    let rel_path = req.get_params().into_values().next().unwrap_or_default();
+                    delete_files.delete(&rel_path, req)
+                },
+            );
+        }
 
-        current_node.handlers.insert(method, Box::new(f));
+        let root_files = Arc::clone(&files);
+        self.add_route(
+            Method::GET,
+            if prefix.is_empty() { "/" } else { prefix },
+            move |req: &Request| root_files.serve("", req),
+        );
+        self.add_route(
+            Method::GET,
+            &format!("{}/{{path}}", prefix),
+            move |req: &Request| {
+                // the param node may be shared with a route that named it differently
+                let rel_path = req.get_params().into_values().next().unwrap_or_default();
+                files.serve(&rel_path, req)
+            },
+        );
     }
 
     pub fn get_handler_and_params(
         &self,
         method: Method,
         origin_form: &str,
-    ) -> (HashMap<String, String>, Option<&Handler>) {
+    ) -> (HashMap<String, String>, Option<&BoxedHandler>) {
+        let (params, node) = self.find_node(origin_form);
+        (params, node.and_then(|node| node.handlers.get(&method)))
+    }
+
+    /// Methods with a handler registered for `origin_form`, used to tell a
+    /// `405` apart from a `404`.
+    pub fn get_allowed_methods(&self, origin_form: &str) -> Vec<Method> {
+        let (_, node) = self.find_node(origin_form);
+        node.map(|node| node.handlers.keys().copied().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn set_fallback<H, Args>(&mut self, h: H)
+    where
+        H: Handler<Args>,
+    {
+        self.fallback = Some(Box::new(move |req| h.call(req)));
+    }
+
+    pub fn get_fallback(&self) -> Option<&BoxedHandler> {
+        self.fallback.as_ref()
+    }
+
+    pub fn get_error_pages(&self) -> &ErrorPages {
+        &self.error_pages
+    }
+
+    pub fn get_error_pages_mut(&mut self) -> &mut ErrorPages {
+        &mut self.error_pages
+    }
+
+    fn find_node(&self, origin_form: &str) -> (HashMap<String, String>, Option<&Node>) {
         let segments = origin_form.split('/').filter(|s| !s.is_empty());
         let mut current_node = &self.root;
         let mut params = HashMap::new();
@@ -86,24 +225,12 @@ impl Router {
                         .to_string(),
                 );
 
-                if let Some(next_node) = current_node.children.get("{param}") {
-                    if let Some(handler) = next_node.handlers.get(&method) {
-                        return (params, Some(handler));
-                    } else {
-                        return (params, None);
-                    }
-                } else {
-                    break;
-                }
+                return (params, current_node.children.get("{param}"));
             } else {
                 return (params, None);
             }
         }
 
-        if let Some(handler) = current_node.handlers.get(&method) {
-            return (params, Some(handler));
-        } else {
-            return (params, None);
-        }
+        (params, Some(current_node))
     }
 }
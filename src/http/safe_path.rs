@@ -0,0 +1,217 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    into_response::IntoResponse,
+    response::{Response, StatusCode},
+};
+
+/// Why a requested path was refused by `resolve_path`.
+#[derive(Debug)]
+pub enum PathError {
+    /// Percent-encoding that doesn't decode to UTF-8.
+    InvalidEncoding,
+    NulByte,
+    Absolute,
+    /// A `..` segment.
+    Traversal,
+    /// The path leaves the root through a symlink.
+    SymlinkEscape,
+    Io(io::Error),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::InvalidEncoding => write!(f, "invalid percent-encoding in path"),
+            PathError::NulByte => write!(f, "NUL byte in path"),
+            PathError::Absolute => write!(f, "absolute path"),
+            PathError::Traversal => write!(f, "`..` segment in path"),
+            PathError::SymlinkEscape => write!(f, "path escapes the root through a symlink"),
+            PathError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl IntoResponse for PathError {
+    fn into_response(self) -> Response {
+        match self {
+            PathError::InvalidEncoding | PathError::NulByte => StatusCode::BadRequest,
+            PathError::Absolute | PathError::Traversal | PathError::SymlinkEscape => {
+                StatusCode::Forbidden
+            }
+            PathError::Io(err) => return err.into_response(),
+        }
+        .into_response()
+    }
+}
+
+/// Decode `%XX` escapes. Fails on malformed escapes or non-UTF-8 results.
+pub fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            // `from_str_radix` would also take a sign, as in `%+f`
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Resolve `requested`, a percent-encoded path taken from a request, to a
+/// location inside `root`.
+///
+/// The path is decoded first so `..%2f` is caught too. `.` and empty
+/// segments are dropped; `..`, absolute paths, backslashes and NUL bytes are
+/// refused outright rather than normalized. Finally the deepest existing
+/// ancestor is canonicalized, so a symlink inside `root` can't point outside
+/// it. The target itself doesn't have to exist, which lets upload routes use
+/// this as well.
+pub fn resolve_path(root: &Path, requested: &str) -> Result<PathBuf, PathError> {
+    let decoded = percent_decode(requested).ok_or(PathError::InvalidEncoding)?;
+    if decoded.contains('\0') {
+        return Err(PathError::NulByte);
+    }
+    if decoded.starts_with('/') || decoded.contains('\\') {
+        return Err(PathError::Absolute);
+    }
+
+    let mut resolved = root.to_path_buf();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return Err(PathError::Traversal),
+            segment => resolved.push(segment),
+        }
+    }
+
+    let canonical_root = root.canonicalize().map_err(PathError::Io)?;
+    let mut existing = resolved.as_path();
+    // lstat, so a dangling symlink counts as existing and fails to canonicalize
+    while existing.symlink_metadata().is_err() {
+        existing = match existing.parent() {
+            Some(parent) => parent,
+            None => break,
+        };
+    }
+    let canonical = existing.canonicalize().map_err(PathError::Io)?;
+    if !canonical.starts_with(&canonical_root) {
+        return Err(PathError::SymlinkEscape);
+    }
+
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// A fresh directory under the system temp dir, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("safe-path-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("a%20b%2Fc").as_deref(), Some("a b/c"));
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%+f"), None);
+        assert_eq!(percent_decode("%-0"), None);
+        assert_eq!(percent_decode("%ff"), None);
+    }
+
+    #[test]
+    fn resolves_paths_inside_the_root() {
+        let root = TempDir::new("inside");
+        fs::create_dir(root.0.join("sub")).unwrap();
+        let resolved = resolve_path(&root.0, "sub/./a%20file.txt").unwrap();
+        assert_eq!(resolved, root.0.join("sub").join("a file.txt"));
+        // missing targets are fine, for uploads
+        let resolved = resolve_path(&root.0, "new/dir//file").unwrap();
+        assert_eq!(resolved, root.0.join("new").join("dir").join("file"));
+    }
+
+    #[test]
+    fn refuses_traversal() {
+        let root = TempDir::new("traversal");
+        for requested in ["..", "../etc/passwd", "a/../../b", "..%2fetc", "%2e%2e/etc"] {
+            assert!(
+                matches!(resolve_path(&root.0, requested), Err(PathError::Traversal)),
+                "{}",
+                requested
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_absolute_paths_backslashes_and_nul() {
+        let root = TempDir::new("absolute");
+        assert!(matches!(
+            resolve_path(&root.0, "/etc/passwd"),
+            Err(PathError::Absolute)
+        ));
+        assert!(matches!(
+            resolve_path(&root.0, "%2fetc/passwd"),
+            Err(PathError::Absolute)
+        ));
+        assert!(matches!(
+            resolve_path(&root.0, "a\\..\\b"),
+            Err(PathError::Absolute)
+        ));
+        assert!(matches!(
+            resolve_path(&root.0, "a%00b"),
+            Err(PathError::NulByte)
+        ));
+        assert!(matches!(
+            resolve_path(&root.0, "a%zz"),
+            Err(PathError::InvalidEncoding)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_out_of_the_root() {
+        let root = TempDir::new("symlink-root");
+        let outside = TempDir::new("symlink-outside");
+        std::os::unix::fs::symlink(&outside.0, root.0.join("escape")).unwrap();
+        std::os::unix::fs::symlink(outside.0.join("missing"), root.0.join("dangling")).unwrap();
+        fs::create_dir(root.0.join("sub")).unwrap();
+        std::os::unix::fs::symlink(root.0.join("sub"), root.0.join("inner")).unwrap();
+
+        assert!(matches!(
+            resolve_path(&root.0, "escape/secret"),
+            Err(PathError::SymlinkEscape)
+        ));
+        assert!(resolve_path(&root.0, "dangling").is_err());
+        assert!(resolve_path(&root.0, "inner/file").is_ok());
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt;
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::cookie::{Cookie, CookieJar};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Secrets shorter than this are refused; they could be brute-forced.
+const MIN_SECRET_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// Separate keys for signing and encryption, derived from one secret so
+/// neither use can weaken the other.
+#[derive(Clone)]
+struct Key {
+    signing: [u8; 32],
+    encryption: [u8; 32],
+}
+
+impl Key {
+    fn derive(secret: &[u8]) -> Self {
+        let derive = |purpose: &[u8]| -> [u8; 32] {
+            let mut mac =
+                <HmacSha256 as Mac>::new_from_slice(secret).expect("HMAC accepts any key length");
+            mac.update(purpose);
+            mac.finalize().into_bytes().into()
+        };
+        Self {
+            signing: derive(b"cookie-signing"),
+            encryption: derive(b"cookie-encryption"),
+        }
+    }
+
+    fn mac(&self, name: &str, value: &str) -> HmacSha256 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.signing)
+            .expect("HMAC accepts any key length");
+        // the name is covered too, so a value can't be moved to another cookie
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&self.encryption.into())
+    }
+}
+
+/// Server secrets for tamper-proof cookies, kept in application state.
+///
+/// Signed cookies stay readable by the client (base64url-encoded) but
+/// can't be altered; encrypted cookies can be neither read nor altered. New
+/// cookies use the current key. Keys retired with `add_old_key` still
+/// verify, so rotating the secret doesn't log everyone out at once.
+///
+/// A cookie that fails verification — tampered, forged, or made with a key
+/// that has since been dropped — reads as missing.
+#[derive(Clone)]
+pub struct CookieKeys {
+    keys: Vec<Key>,
+}
+
+impl fmt::Debug for CookieKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CookieKeys")
+            .field("keys", &self.keys.len())
+            .finish()
+    }
+}
+
+impl CookieKeys {
+    /// Panics if `secret` is shorter than 32 bytes.
+    pub fn new(secret: &[u8]) -> Self {
+        assert_secret_len(secret);
+        Self {
+            keys: vec![Key::derive(secret)],
+        }
+    }
+
+    /// Accept cookies made with a previous secret, without using it for new
+    /// ones. Panics if `secret` is shorter than 32 bytes.
+    pub fn add_old_key(&mut self, secret: &[u8]) {
+        assert_secret_len(secret);
+        self.keys.push(Key::derive(secret));
+    }
+
+    /// Replace the cookie's value with a signed copy of it. The value is
+    /// base64url-encoded, so any string survives the trip through the
+    /// `Cookie` header.
+    pub fn sign(&self, cookie: &mut Cookie) {
+        let tag = self.keys[0]
+            .mac(cookie.get_name(), cookie.get_value())
+            .finalize()
+            .into_bytes();
+        let signed = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(cookie.get_value()),
+            URL_SAFE_NO_PAD.encode(tag)
+        );
+        cookie.set_value(&signed);
+    }
+
+    /// The original value of a signed cookie, if any key's signature
+    /// matches.
+    pub fn verify(&self, name: &str, signed: &str) -> Option<String> {
+        let (value, tag) = signed.split_once('.')?;
+        let value = String::from_utf8(URL_SAFE_NO_PAD.decode(value).ok()?).ok()?;
+        let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+        self.keys
+            .iter()
+            .any(|key| key.mac(name, &value).verify_slice(&tag).is_ok())
+            .then_some(value)
+    }
+
+    /// Replace the cookie's value with its encryption under a fresh nonce.
+    pub fn encrypt(&self, cookie: &mut Cookie) {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: cookie.get_value().as_bytes(),
+            aad: cookie.get_name().as_bytes(),
+        };
+        let ciphertext = self.keys[0]
+            .cipher()
+            .encrypt(&nonce, payload)
+            .expect("AES-GCM encryption of a cookie can't fail");
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        cookie.set_value(&URL_SAFE_NO_PAD.encode(sealed));
+    }
+
+    /// The original value of an encrypted cookie, if any key opens it.
+    pub fn decrypt(&self, name: &str, sealed: &str) -> Option<String> {
+        let sealed = URL_SAFE_NO_PAD.decode(sealed).ok()?;
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::from_slice(nonce);
+        self.keys.iter().find_map(|key| {
+            let payload = Payload {
+                msg: ciphertext,
+                aad: name.as_bytes(),
+            };
+            let plaintext = key.cipher().decrypt(nonce, payload).ok()?;
+            String::from_utf8(plaintext).ok()
+        })
+    }
+}
+
+fn assert_secret_len(secret: &[u8]) {
+    assert!(
+        secret.len() >= MIN_SECRET_LEN,
+        "cookie secrets must be at least {} bytes",
+        MIN_SECRET_LEN
+    );
+}
+
+impl CookieJar {
+    /// The value of signed cookie `name`, or `None` if it is missing or its
+    /// signature doesn't verify.
+    pub fn get_signed(&self, name: &str, keys: &CookieKeys) -> Option<String> {
+        keys.verify(name, self.get(name)?)
+    }
+
+    /// The value of encrypted cookie `name`, or `None` if it is missing or
+    /// can't be decrypted.
+    pub fn get_encrypted(&self, name: &str, keys: &CookieKeys) -> Option<String> {
+        keys.decrypt(name, self.get(name)?)
+    }
+}
+
+#[cfg(all(test, feature = "secure-cookies"))]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+    const OLD_SECRET: &[u8] = b"fedcba9876543210fedcba9876543210";
+
+    fn signed(keys: &CookieKeys, name: &str, value: &str) -> String {
+        let mut cookie = Cookie::new(name, value);
+        keys.sign(&mut cookie);
+        cookie.get_value().to_string()
+    }
+
+    fn encrypted(keys: &CookieKeys, name: &str, value: &str) -> String {
+        let mut cookie = Cookie::new(name, value);
+        keys.encrypt(&mut cookie);
+        cookie.get_value().to_string()
+    }
+
+    #[test]
+    fn signed_values_round_trip() {
+        let keys = CookieKeys::new(SECRET);
+        for value in ["", "plain", "a; b=c, \"d\" é"] {
+            let signed = signed(&keys, "user", value);
+            assert_eq!(keys.verify("user", &signed).as_deref(), Some(value));
+            // the encoded value is safe to send back in a `Cookie` header
+            let jar = CookieJar::parse(&format!("user={}", signed));
+            assert_eq!(jar.get_signed("user", &keys).as_deref(), Some(value));
+        }
+    }
+
+    #[test]
+    fn tampered_signatures_fail() {
+        let keys = CookieKeys::new(SECRET);
+        let signed = signed(&keys, "user", "alice");
+        let (_, tag) = signed.split_once('.').unwrap();
+        let forged = format!("{}.{}", URL_SAFE_NO_PAD.encode("admin"), tag);
+        assert_eq!(keys.verify("user", &forged), None);
+        // a value signed for one cookie can't be moved to another
+        assert_eq!(keys.verify("role", &signed), None);
+        assert_eq!(keys.verify("user", "alice"), None);
+        assert_eq!(keys.verify("user", &signed[..signed.len() - 1]), None);
+        let other = CookieKeys::new(OLD_SECRET);
+        assert_eq!(other.verify("user", &signed), None);
+    }
+
+    #[test]
+    fn old_keys_verify_but_do_not_sign() {
+        let old = CookieKeys::new(OLD_SECRET);
+        let signed_before = signed(&old, "user", "alice");
+        let sealed_before = encrypted(&old, "user", "alice");
+
+        let mut keys = CookieKeys::new(SECRET);
+        assert_eq!(keys.verify("user", &signed_before), None);
+        keys.add_old_key(OLD_SECRET);
+        assert_eq!(
+            keys.verify("user", &signed_before).as_deref(),
+            Some("alice")
+        );
+        assert_eq!(
+            keys.decrypt("user", &sealed_before).as_deref(),
+            Some("alice")
+        );
+        // new cookies use the current key only
+        assert_eq!(old.verify("user", &signed(&keys, "user", "alice")), None);
+    }
+
+    #[test]
+    fn encrypted_values_round_trip() {
+        let keys = CookieKeys::new(SECRET);
+        let sealed = encrypted(&keys, "session", "secret value");
+        assert!(!sealed.contains("secret"));
+        assert_ne!(sealed, encrypted(&keys, "session", "secret value"));
+        let jar = CookieJar::parse(&format!("session={}", sealed));
+        assert_eq!(
+            jar.get_encrypted("session", &keys).as_deref(),
+            Some("secret value")
+        );
+    }
+
+    #[test]
+    fn decryption_is_bound_to_the_name() {
+        let keys = CookieKeys::new(SECRET);
+        let sealed = encrypted(&keys, "session", "secret value");
+        assert_eq!(keys.decrypt("other", &sealed), None);
+        let mut tampered = URL_SAFE_NO_PAD.decode(&sealed).unwrap();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            keys.decrypt("session", &URL_SAFE_NO_PAD.encode(tampered)),
+            None
+        );
+        assert_eq!(keys.decrypt("session", "c2hvcnQ"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 32 bytes")]
+    fn refuses_short_secrets() {
+        CookieKeys::new(b"too short");
+    }
+}
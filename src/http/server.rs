@@ -1,162 +1,644 @@
-use bytes::Buf;
-use flate2::Compression;
 use std::{
     any::Any,
-    collections::HashMap,
-    io::{BufReader, Read, Write},
+    io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Write},
     net::{TcpListener, TcpStream},
-    sync::{Arc, Mutex},
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, PoisonError, RwLock},
     thread,
+    time::SystemTime,
 };
 
 use crate::http::request::Request;
-use flate2::write::GzEncoder;
 
 use super::{
+    chunked::{ChunkedReader, ChunkedWriter},
+    compression::{self, CompressionPolicy},
+    conditional::{self, ETag, Validators},
+    encoding::{self, DecodeError, DecodingReader},
     error::HTTPError,
-    request::{HTTPVersion, RequestBody},
+    error_pages::ErrorFormat,
+    extensions::Extensions,
+    files::StaticFiles,
+    handler::Handler,
+    into_response::IntoResponse,
+    middleware::{self, Next},
+    request::{BodyReader, RequestBody},
     response::{Response, StatusCode},
     router::Router,
-    Encoding, Method,
+    Encoding, EncodingConfig, EncodingType, Method, NotAcceptable,
 };
 
+/// Settings fixed once the server starts running.
+#[derive(Debug)]
+struct Config {
+    encodings: EncodingConfig,
+    compression: CompressionPolicy,
+    max_body_size: usize,
+    max_decoded_body_size: usize,
+    hash_etags: bool,
+    server_header: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            encodings: EncodingConfig::default(),
+            compression: CompressionPolicy::default(),
+            max_body_size: 16 * 1024 * 1024,
+            max_decoded_body_size: 16 * 1024 * 1024,
+            hash_etags: false,
+            server_header: Some(format!(
+                "{}/{}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            )),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Server {
     listener: TcpListener,
-    pub router: Arc<Mutex<Router>>,
+    pub router: Arc<RwLock<Router>>,
+    state: Arc<Extensions>,
+    config: Arc<Config>,
 }
 
 impl Server {
     pub fn new(addr: &str) -> Result<Self, std::io::Error> {
         let listener = TcpListener::bind(addr)?;
         println!("Started listening from the server");
-        let router = Arc::new(Mutex::new(Router::new()));
-        Ok(Self { listener, router })
+        let router = Arc::new(RwLock::new(Router::new()));
+        let state = Arc::new(Extensions::new());
+        let config = Arc::new(Config::default());
+        Ok(Self {
+            listener,
+            router,
+            state,
+            config,
+        })
+    }
+
+    pub fn add_route<H, Args>(&self, method: Method, p: &str, h: H)
+    where
+        H: Handler<Args>,
+    {
+        self.router
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .add_route(method, p, h);
     }
 
-    pub fn add_route<F>(&self, method: Method, p: &str, f: F)
+    /// Register a handler that reads the request body from the connection
+    /// as it arrives instead of receiving it buffered; see
+    /// `Router::add_streaming_route`.
+    pub fn add_streaming_route<H, Args>(&self, method: Method, p: &str, h: H)
     where
-        F: Fn(&Request) -> Response + 'static + Send + Sync,
+        H: Handler<Args>,
     {
-        self.router.lock().unwrap().add_route(method, p, f);
+        self.router
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .add_streaming_route(method, p, h);
+    }
+
+    /// Serve the files of `files` under `prefix`, e.g. `/files/{path}`.
+    pub fn mount(&self, prefix: &str, files: StaticFiles) {
+        self.router
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .mount(prefix, files);
+    }
+
+    /// Register middleware that runs before every matched route handler.
+    ///
+    /// Middleware may modify the request (e.g. attach extensions), call `next`
+    /// to continue the chain, or return its own response to short-circuit it.
+    /// The body is still unread while middleware runs, so a request turned
+    /// away here is answered before a client sending `Expect: 100-continue`
+    /// uploads anything.
+    pub fn add_middleware<F>(&self, f: F)
+    where
+        F: Fn(&mut Request, Next) -> Response + 'static + Send + Sync,
+    {
+        self.router
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .add_middleware(f);
+    }
+
+    /// Handle requests that match no route. Paths registered for other
+    /// methods still get a `405`.
+    pub fn set_fallback<H, Args>(&self, h: H)
+    where
+        H: Handler<Args>,
+    {
+        self.router
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .set_fallback(h);
+    }
+
+    /// Render the body of `status` error responses that don't carry one,
+    /// including router misses, `405`s, parse errors and handler failures.
+    pub fn set_error_renderer<F, R>(&self, status: StatusCode, f: F)
+    where
+        F: Fn(StatusCode, ErrorFormat) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.router
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_error_pages_mut()
+            .set_renderer(status, f);
+    }
+
+    /// Make `value` available to every handler through `Request::get_state`.
+    ///
+    /// State can only be added before the server starts running.
+    pub fn add_state<T: Send + Sync + 'static>(&mut self, value: T) {
+        Arc::get_mut(&mut self.state)
+            .expect("state must be added before the server starts")
+            .insert(value);
+    }
+
+    /// Set which response codings are offered, in preference order. Earlier
+    /// entries win when the client's q-values tie.
+    pub fn set_encodings(&mut self, preference: &[EncodingType]) {
+        self.config_mut().encodings.set_preference(preference);
+    }
+
+    pub fn set_compression_level(&mut self, encoding_type: EncodingType, level: u32) {
+        self.config_mut().encodings.set_level(encoding_type, level);
+    }
+
+    /// Control which responses get compressed: minimum body size and the
+    /// content types that are allowed or denied.
+    pub fn set_compression_policy(&mut self, policy: CompressionPolicy) {
+        self.config_mut().compression = policy;
+    }
+
+    /// Cap on the request body the server buffers for a handler. A larger
+    /// `Content-Length` is answered with `413` before the body is read.
+    /// Streaming routes aren't limited.
+    pub fn set_max_body_size(&mut self, size: usize) {
+        self.config_mut().max_body_size = size;
     }
 
+    /// Cap on the size of a request body after undoing its
+    /// `Content-Encoding`. Larger bodies are rejected with `413`.
+    pub fn set_max_decoded_body_size(&mut self, size: usize) {
+        self.config_mut().max_decoded_body_size = size;
+    }
+
+    /// Give `GET` responses with an in-memory body and no `ETag` of their
+    /// own a tag hashed from the body, so clients can revalidate them.
+    pub fn set_hash_etags(&mut self, enabled: bool) {
+        self.config_mut().hash_etags = enabled;
+    }
+
+    /// The `Server` header sent with every response that doesn't set its
+    /// own, or `None` to leave it out. Defaults to the crate name and
+    /// version.
+    pub fn set_server_header(&mut self, value: Option<&str>) {
+        self.config_mut().server_header = value.map(|value| value.to_string());
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        Arc::get_mut(&mut self.config).expect("config must be changed before the server starts")
+    }
+
+    /// Read the request line and headers. The body is left on the
+    /// connection behind a `BodyReader`, to be buffered or streamed once the
+    /// route is known.
     fn read_request(stream: TcpStream) -> Result<Request, HTTPError> {
-        let buf_reader = BufReader::new(stream);
+        let mut buf_reader = BufReader::new(stream);
         let mut bytes: Vec<u8> = Vec::new();
-        let mut byte_iter = buf_reader.bytes();
-        while let Some(byte) = byte_iter.next() {
-            // read only until header and parse the body later according to contet length and type
-            if bytes.len() >= 4 && &bytes[(bytes.len() - 3)..] == b"\r\n\r" {
-                break;
+        // read only until the blank line ending the headers
+        while !bytes.ends_with(b"\r\n\r\n") {
+            if buf_reader.read_until(b'\n', &mut bytes)? == 0 {
+                return Err(HTTPError::Custom);
             }
-            bytes.push(byte.unwrap());
         }
-        let mut req = Request::from(bytes);
+        let mut req = Request::try_from(bytes)?;
 
-        // read body based on Content-Length property
-        let headers = req.get_headers();
-        let length = match headers
-            .get("content-length")
-            .unwrap_or(&String::new())
-            .parse::<usize>()
-        {
-            Ok(l) => l,
-            Err(_) => 0,
+        let chunked = match req.get_headers().get("transfer-encoding") {
+            None => false,
+            // a body framed both ways is how requests get smuggled
+            Some(_) if req.get_headers().contains_key("content-length") => {
+                return Err(HTTPError::Custom)
+            }
+            Some(coding) if coding.trim().eq_ignore_ascii_case("chunked") => true,
+            Some(_) => return Err(HTTPError::NotImplemented),
         };
-
-        let body_bytes = byte_iter
-            .take(length)
-            .map(|x| x.unwrap())
-            .collect::<Vec<u8>>();
-        req.set_body(RequestBody::String(Vec::from(body_bytes)));
+        let length = match req.get_headers().get("content-length") {
+            Some(length) => length.parse::<u64>().map_err(|_| HTTPError::Custom)?,
+            None => 0,
+        };
+        let expects_continue = req
+            .get_headers()
+            .get("expect")
+            .is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue"));
+        let continue_writer = match expects_continue {
+            true => Some(buf_reader.get_ref().try_clone()?),
+            false => None,
+        };
+        let mut body = match chunked {
+            true => {
+                // handlers see the body with its chunk framing removed
+                req.remove_header("transfer-encoding");
+                BodyReader::until_eof(ChunkedReader::new(buf_reader))
+            }
+            false => BodyReader::new(buf_reader, length),
+        };
+        if let Some(writer) = continue_writer {
+            body.set_continue_writer(writer);
+        }
+        req.set_body_reader(body);
         Ok(req)
     }
 
-    fn return_response(mut stream: TcpStream, res: &[u8]) -> Result<(), std::io::Error> {
-        stream.write_all(&res[..])?;
-        Ok(())
-    }
+    /// Settle the headers of the response about to be sent. The framing
+    /// headers are the server's: `Content-Length` is worked out from the
+    /// body actually held, a stream whose length wasn't declared is chunked,
+    /// and whatever the handler set that contradicts this is dropped.
+    fn finalize_headers(res: &mut Response, config: &Config) {
+        let declared_length = res
+            .remove_header("Content-Length")
+            .and_then(|length| length.trim().parse::<u64>().ok());
+        res.remove_header("Transfer-Encoding");
 
-    fn process_request(mut req: Request, router: &Router) -> Response {
-        let (params, handler) =
-            router.get_handler_and_params(req.get_method(), req.get_target().as_str());
-        req.set_params(params);
-        match handler {
-            Some(h) => {
-                let mut res = h(&req);
-                let h = req.get_headers();
-                let mut headers = res.get_headers();
-                let d = String::new();
-                if h.get("accept-encoding").is_some() {
-                    let body = res.get_body();
-
-                    let body_buf = match body {
-                        Some(RequestBody::String(bytes)) => bytes,
-                        None => Vec::new(),
-                    };
-                    let mut encoder = GzEncoder::new(vec![], Compression::default());
+        if res.get_status_code().allows_body() {
+            match res.get_body() {
+                Some(RequestBody::String(bytes)) => {
+                    res.set_header("Content-Length", &bytes.len().to_string())
+                }
+                None if res.has_stream() => match declared_length {
+                    Some(length) => res.set_header("Content-Length", &length.to_string()),
+                    None => res.set_header("Transfer-Encoding", "chunked"),
+                },
+                None => res.set_header("Content-Length", "0"),
+            }
+        }
 
-                    encoder.write_all(&body_buf);
+        if res.get_header("Date").is_none() {
+            res.set_header("Date", &httpdate::fmt_http_date(SystemTime::now()));
+        }
+        if let Some(server) = &config.server_header {
+            if res.get_header("Server").is_none() {
+                res.set_header("Server", server);
+            }
+        }
+    }
 
-                    let compressed_buf = encoder.finish();
+    /// Write `res`, whose headers `finalize_headers` has settled.
+    fn return_response(stream: TcpStream, mut res: Response) -> Result<(), std::io::Error> {
+        let mut writer = BufWriter::new(stream);
+        writer.write_all(res.to_string().as_bytes())?;
+        if !res.get_status_code().allows_body() {
+            return writer.flush();
+        }
 
-                    if let Ok(cp) = compressed_buf {
-                        headers.insert("Content-Length".to_string(), cp.len().to_string());
-                        let encoding_str = h.get("accept-encoding").unwrap_or(&d);
-                        let encoding = Encoding::get_endoing_scheme(encoding_str);
-                        if let Some(enc) = encoding {
-                            headers.insert("Content-Encoding".to_string(), enc.to_string());
-                        }
-                        res.set_body(RequestBody::String(cp));
+        match res.take_stream() {
+            Some(mut reader) => {
+                let length = res
+                    .get_header("Content-Length")
+                    .and_then(|length| length.parse::<u64>().ok());
+                match length {
+                    // a stream longer than it claimed would corrupt the framing
+                    Some(length) => {
+                        io::copy(&mut reader.take(length), &mut writer)?;
+                    }
+                    None => {
+                        let mut chunked_writer = ChunkedWriter::new(writer);
+                        io::copy(&mut reader, &mut chunked_writer)?;
+                        writer = chunked_writer.finish()?;
                     }
                 }
+            }
+            None => {
+                if let Some(RequestBody::String(bytes)) = res.get_body() {
+                    writer.write_all(&bytes)?;
+                }
+            }
+        }
+        writer.flush()
+    }
+
+    fn process_request(
+        mut req: Request,
+        router: &Router,
+        state: Arc<Extensions>,
+        config: &Config,
+    ) -> Response {
+        let path = req.get_path();
+        let (params, handler) = router.get_handler_and_params(req.get_method(), path.as_str());
+        req.set_params(params);
+        req.set_state(state);
+
+        // `100-continue` is the only expectation there is to meet
+        let expect = req.get_headers().get("expect");
+        if expect.is_some_and(|expect| !expect.eq_ignore_ascii_case("100-continue")) {
+            let res = StatusCode::ExpectationFailed.into_response();
+            return router.get_error_pages().apply(res, Some(&req));
+        }
+
+        // negotiate up front so a 406 never runs the handler's side effects
+        let accept_encoding = req.get_headers().get("accept-encoding");
+        let encoding = match Encoding::negotiate(
+            accept_encoding.map(|a| a.as_str()),
+            config.encodings.get_preference(),
+        ) {
+            Ok(encoding) => encoding,
+            Err(NotAcceptable) => {
+                let res = StatusCode::NotAcceptable.into_response();
+                return router.get_error_pages().apply(res, Some(&req));
+            }
+        };
+
+        // a path registered for other methods is a 405, not a miss for the fallback
+        let allowed = match handler {
+            Some(_) => Vec::new(),
+            None => router.get_allowed_methods(path.as_str()),
+        };
+        let handler = match handler {
+            Some(h) => Some(h),
+            None if allowed.is_empty() => router.get_fallback(),
+            None => None,
+        };
 
-                headers
-                    .entry("Content-Type".to_string())
-                    .or_insert("text/plain".to_string());
-                res.set_headers(headers);
+        let streaming = router.is_streaming(req.get_method(), path.as_str());
+        let res = match handler {
+            Some(h) => Server::call_handler(&mut req, |req| {
+                middleware::run_chain(router.get_middlewares(), req, &|req| {
+                    // read only now, so middleware can turn a request away
+                    // before a client waiting on `100 Continue` sends the body
+                    let body = match streaming {
+                        true => Server::decode_body_stream(req, config),
+                        false => Server::read_body(req, config),
+                    };
+                    if let Err(res) = body {
+                        return res;
+                    }
+                    h(req)
+                })
+            }),
+            None if allowed.is_empty() => StatusCode::NotFound.into_response(),
+            None => {
+                let mut allowed = allowed.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+                allowed.sort();
+                let mut res = StatusCode::MethodNotAllowed.into_response();
+                res.set_header("Allow", &allowed.join(", "));
                 res
             }
-            None => Response::new(HTTPVersion::HTTP1_1, HashMap::new(), StatusCode::NotFound),
+        };
+        let res = Server::apply_preconditions(&req, res, encoding, config);
+        let mut res = router.get_error_pages().apply(res, Some(&req));
+
+        Server::compress_response(&mut res, encoding, config);
+
+        if res.get_header("Content-Type").is_none() && res.get_status_code().allows_body() {
+            res.set_header("Content-Type", "text/plain");
         }
+        res
     }
 
-    fn handle_connection(stream: TcpStream, router: Arc<Mutex<Router>>) {
-        println!("Connected to server: Client: {:?}", stream.type_id());
-        let req = Server::read_request(stream.try_clone().unwrap());
-
-        match req {
-            Ok(req) => {
-                let resp = Server::process_request(req, &Arc::clone(&router).lock().unwrap());
-                let body = resp.get_body();
-                match body {
-                    Some(RequestBody::String(bytes)) => {
-                        Server::return_response(
-                            stream,
-                            &[resp.to_string().as_bytes(), &bytes[..]].concat(),
-                        );
-                    }
-                    None => {
-                        Server::return_response(stream, resp.to_string().as_bytes());
-                    }
+    /// Answer a conditional `GET` from the handler's response: `304` when
+    /// the client's copy is current, `412` when a precondition fails.
+    /// Unsafe methods are left to their handlers, which must check before
+    /// acting rather than after.
+    ///
+    /// The validators are those of the representation `encoding` will turn
+    /// the response into, so a `304` carries the same `ETag` and `Vary` as
+    /// the `200` it stands in for.
+    fn apply_preconditions(
+        req: &Request,
+        mut res: Response,
+        encoding: EncodingType,
+        config: &Config,
+    ) -> Response {
+        let status = res.get_status_code();
+        if req.get_method() != Method::GET
+            || !matches!(status, StatusCode::Ok | StatusCode::PartialContent)
+        {
+            return res;
+        }
+
+        if config.hash_etags && res.get_header("ETag").is_none() {
+            if let Some(RequestBody::String(bytes)) = res.get_body() {
+                res.set_header("ETag", &ETag::from_bytes(&bytes).to_string());
+            }
+        }
+        if config.compression.should_compress(&res) {
+            compression::add_vary(&mut res, "Accept-Encoding");
+            if encoding != EncodingType::Identity {
+                conditional::weaken_etag(&mut res);
+            }
+        }
+
+        match conditional::evaluate(req, Some(&Validators::from_response(&res))) {
+            Some(StatusCode::NotModified) => conditional::not_modified(&res),
+            Some(status) => {
+                // cookies set along the way, e.g. a session's, still apply
+                let mut failed = status.into_response();
+                for cookie in res.get_cookies() {
+                    failed.add_cookie(cookie.clone());
                 }
+                failed
+            }
+            None => res,
+        }
+    }
+
+    /// Buffer the body for a handler that doesn't stream it, then undo its
+    /// `Content-Encoding`.
+    fn read_body(req: &mut Request, config: &Config) -> Result<(), Response> {
+        let length = req
+            .get_headers()
+            .get("content-length")
+            .and_then(|length| length.parse::<u64>().ok())
+            .unwrap_or_default();
+        if length > config.max_body_size as u64 {
+            return Err(StatusCode::PayloadTooLarge.into_response());
+        }
+        // a chunked body only shows its size as it is read
+        match req.buffer_body(config.max_body_size as u64) {
+            Ok(true) => {}
+            Ok(false) => return Err(StatusCode::PayloadTooLarge.into_response()),
+            Err(_) => return Err(StatusCode::BadRequest.into_response()),
+        }
+
+        Server::decode_request_body(req, config).map_err(|status| match status {
+            StatusCode::UnsupportedMediaType => Server::unsupported_encoding(),
+            status => status.into_response(),
+        })
+    }
+
+    /// On a streaming route, have the body decoded from its
+    /// `Content-Encoding` as the handler reads it, up to the decoded size
+    /// limit. A coding the server doesn't know is refused before the body
+    /// is read.
+    fn decode_body_stream(req: &mut Request, config: &Config) -> Result<(), Response> {
+        let content_encoding = match req.get_headers().get("content-encoding") {
+            Some(content_encoding) => content_encoding.clone(),
+            None => return Ok(()),
+        };
+        let codings = encoding::parse_content_encoding(&content_encoding)
+            .map_err(|_| Server::unsupported_encoding())?;
+
+        let body = req.take_body_reader();
+        let decoded = DecodingReader::new(body, codings, config.max_decoded_body_size);
+        req.set_body_reader(BodyReader::until_eof(decoded));
+        req.remove_header("content-encoding");
+        // the decoded length is only known once it has all been read
+        req.remove_header("content-length");
+        Ok(())
+    }
+
+    /// `415` for a request body in a coding the server can't decode,
+    /// listing the ones it can.
+    fn unsupported_encoding() -> Response {
+        let supported = EncodingType::all()
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        let mut res = StatusCode::UnsupportedMediaType.into_response();
+        res.set_header("Accept-Encoding", &supported.join(", "));
+        res
+    }
+
+    /// Replace a `Content-Encoding`-coded body with its decoded form so
+    /// handlers always see the original bytes.
+    fn decode_request_body(req: &mut Request, config: &Config) -> Result<(), StatusCode> {
+        let content_encoding = match req.get_headers().get("content-encoding") {
+            Some(content_encoding) => content_encoding.clone(),
+            None => return Ok(()),
+        };
+        let body = match req.get_body() {
+            Some(RequestBody::String(bytes)) => bytes,
+            None => Vec::new(),
+        };
+
+        let decoded =
+            encoding::decode_content(&content_encoding, body, config.max_decoded_body_size)
+                .map_err(|err| match err {
+                    DecodeError::Unsupported(_) => StatusCode::UnsupportedMediaType,
+                    DecodeError::TooLarge => StatusCode::PayloadTooLarge,
+                    DecodeError::Corrupt(_) => StatusCode::BadRequest,
+                })?;
+        req.remove_header("content-encoding");
+        req.set_header("content-length", &decoded.len().to_string());
+        req.set_body(RequestBody::String(decoded));
+        Ok(())
+    }
+
+    /// Compress the body with `encoding` when the policy allows it, and mark
+    /// every response the policy covers with `Vary: Accept-Encoding`.
+    ///
+    /// Streamed and large bodies are wrapped in an encoder that compresses as
+    /// the body is written out, so memory stays bounded.
+    fn compress_response(res: &mut Response, encoding: EncodingType, config: &Config) {
+        if !config.compression.should_compress(res) {
+            return;
+        }
+        compression::add_vary(res, "Accept-Encoding");
+        if encoding == EncodingType::Identity {
+            return;
+        }
+
+        let level = config.encodings.get_level(encoding);
+        let body_buf = match res.get_body() {
+            Some(RequestBody::String(bytes)) => bytes,
+            None => Vec::new(),
+        };
+        if res.has_stream() || body_buf.len() > config.compression.get_stream_threshold() {
+            let reader: Box<dyn Read + Send> = match res.take_stream() {
+                Some(reader) => reader,
+                None => Box::new(Cursor::new(body_buf)),
+            };
+            if let Ok(encoder) = encoding.encoder(reader, level) {
+                res.set_stream(encoder);
+                res.remove_header("Content-Length");
+                res.set_header("Content-Encoding", &encoding.to_string());
+                conditional::weaken_etag(res);
             }
-            Err(_) => {
+            return;
+        }
+
+        if let Ok(cp) = config.encodings.encode(encoding, &body_buf) {
+            res.set_header("Content-Encoding", &encoding.to_string());
+            res.set_body(RequestBody::String(cp));
+            conditional::weaken_etag(res);
+        }
+    }
+
+    /// Invoke `f`, turning a panic into a `500` so it never takes down the
+    /// connection thread or leaves shared state poisoned.
+    fn call_handler<F>(req: &mut Request, f: F) -> Response
+    where
+        F: FnOnce(&mut Request) -> Response,
+    {
+        let request_line = format!("{} {}", req.get_method(), req.get_target());
+        match panic::catch_unwind(AssertUnwindSafe(|| f(req))) {
+            Ok(res) => res,
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| payload.downcast_ref::<String>().map(|s| s.as_str()))
+                    .unwrap_or("unknown panic");
+                eprintln!("Handler panicked on `{}`: {}", request_line, message);
+                StatusCode::InternalServerError.into_response()
+            }
+        }
+    }
+
+    fn handle_connection(
+        stream: TcpStream,
+        router: Arc<RwLock<Router>>,
+        state: Arc<Extensions>,
+        config: Arc<Config>,
+    ) {
+        println!("Connected to server: Client: {:?}", stream.type_id());
+        let router = router.read().unwrap_or_else(PoisonError::into_inner);
+        let mut resp = match Server::read_request(stream.try_clone().unwrap()) {
+            Ok(req) => Server::process_request(req, &router, state, &config),
+            Err(HTTPError::Custom) => {
                 eprintln!("Error in parsing request");
+                router
+                    .get_error_pages()
+                    .render(StatusCode::BadRequest, None)
+            }
+            Err(HTTPError::NotImplemented) => router
+                .get_error_pages()
+                .render(StatusCode::NotImplemented, None),
+            Err(HTTPError::Io(err)) => {
+                eprintln!("Error in reading request: {}", err);
+                return;
             }
         };
+
+        Server::finalize_headers(&mut resp, &config);
+        if let Err(err) = Server::return_response(stream, resp) {
+            eprintln!("Error in writing response: {}", err);
+        }
     }
 
     pub fn run(&mut self) {
         for stream in self.listener.try_clone().unwrap().incoming() {
             let router = Arc::clone(&self.router);
+            let state = Arc::clone(&self.state);
+            let config = Arc::clone(&self.config);
             match stream {
                 Ok(stream) => {
                     thread::spawn(move || {
-                        Server::handle_connection(stream.try_clone().unwrap(), router)
+                        Server::handle_connection(
+                            stream.try_clone().unwrap(),
+                            router,
+                            state,
+                            config,
+                        )
                     });
-                    ()
                 }
 
                 Err(err) => {
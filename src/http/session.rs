@@ -0,0 +1,466 @@
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    cookie::{Cookie, SameSite},
+    extract::{FromRequest, Rejection},
+    files::{write_file, FileError},
+    into_response::IntoResponse,
+    middleware::Next,
+    request::Request,
+    response::{Response, StatusCode},
+};
+
+/// Random bytes in a session ID, which is sent hex-encoded.
+const ID_LEN: usize = 32;
+
+/// A stored session: its data and when it was created and last used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionRecord {
+    pub data: HashMap<String, String>,
+    pub created: SystemTime,
+    pub last_access: SystemTime,
+}
+
+impl SessionRecord {
+    /// Whether the session has been idle for longer than `idle_timeout` or
+    /// has outlived `absolute_timeout`.
+    pub fn is_expired(
+        &self,
+        now: SystemTime,
+        idle_timeout: Duration,
+        absolute_timeout: Duration,
+    ) -> bool {
+        let age = |since: SystemTime| now.duration_since(since).unwrap_or_default();
+        age(self.last_access) > idle_timeout || age(self.created) > absolute_timeout
+    }
+}
+
+/// Where sessions are kept between requests.
+///
+/// IDs passed in are always ones generated by `Sessions`: 64 lowercase hex
+/// digits, safe to use as a file name or key.
+pub trait SessionStore: Send + Sync {
+    fn load(&self, id: &str) -> io::Result<Option<SessionRecord>>;
+
+    fn save(&self, id: &str, record: &SessionRecord) -> io::Result<()>;
+
+    fn remove(&self, id: &str) -> io::Result<()>;
+
+    /// Remove every session `is_expired` returns `true` for.
+    fn remove_expired(&self, is_expired: &dyn Fn(&SessionRecord) -> bool) -> io::Result<()>;
+}
+
+/// Keeps sessions in memory; they are lost when the server stops.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, SessionRecord>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn sessions(&self) -> std::sync::MutexGuard<'_, HashMap<String, SessionRecord>> {
+        self.sessions.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> io::Result<Option<SessionRecord>> {
+        Ok(self.sessions().get(id).cloned())
+    }
+
+    fn save(&self, id: &str, record: &SessionRecord) -> io::Result<()> {
+        self.sessions().insert(id.to_string(), record.clone());
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        self.sessions().remove(id);
+        Ok(())
+    }
+
+    fn remove_expired(&self, is_expired: &dyn Fn(&SessionRecord) -> bool) -> io::Result<()> {
+        self.sessions().retain(|_, record| !is_expired(record));
+        Ok(())
+    }
+}
+
+/// Keeps each session in a file named after its ID, so sessions survive a
+/// restart and can be shared by servers on the same machine.
+///
+/// A file that can't be parsed reads as a missing session.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    /// Store sessions in `dir`, creating it if needed.
+    pub fn new(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, id: &str) -> io::Result<PathBuf> {
+        if !is_valid_id(id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid session ID",
+            ));
+        }
+        Ok(self.dir.join(id))
+    }
+
+    /// The first line holds the creation and last access times in seconds
+    /// since the epoch, the second the data, form-encoded.
+    fn encode(record: &SessionRecord) -> String {
+        let secs = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        };
+        let data = serde_urlencoded::to_string(&record.data).unwrap_or_default();
+        format!(
+            "{} {}\n{}\n",
+            secs(record.created),
+            secs(record.last_access),
+            data
+        )
+    }
+
+    fn decode(contents: &str) -> Option<SessionRecord> {
+        let (times, data) = contents.split_once('\n')?;
+        let (created, last_access) = times.split_once(' ')?;
+        let time = |secs: &str| {
+            secs.parse()
+                .ok()
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+        };
+        Some(SessionRecord {
+            data: serde_urlencoded::from_str(data.trim_end()).ok()?,
+            created: time(created)?,
+            last_access: time(last_access)?,
+        })
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> io::Result<Option<SessionRecord>> {
+        match fs::read_to_string(self.path(id)?) {
+            Ok(contents) => Ok(Self::decode(&contents)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn save(&self, id: &str, record: &SessionRecord) -> io::Result<()> {
+        write_file(&self.path(id)?, Self::encode(record).as_bytes(), false)
+            .map(|_| ())
+            .map_err(|err| match err {
+                FileError::Io(err) => err,
+                err => io::Error::other(err.to_string()),
+            })
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(id)?) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn remove_expired(&self, is_expired: &dyn Fn(&SessionRecord) -> bool) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            // skips temporary files left by an interrupted save
+            if !name.to_str().is_some_and(is_valid_id) {
+                continue;
+            }
+            let record = fs::read_to_string(entry.path())
+                .ok()
+                .and_then(|contents| Self::decode(&contents));
+            // unreadable sessions can never be loaded, so they go too
+            let expired = match record {
+                Some(record) => is_expired(&record),
+                None => true,
+            };
+            if expired {
+                self.remove(&name.to_string_lossy())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_valid_id(id: &str) -> bool {
+    id.len() == ID_LEN * 2 && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn generate_id() -> io::Result<String> {
+    let mut bytes = [0u8; ID_LEN];
+    getrandom::getrandom(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[derive(Debug)]
+struct SessionState {
+    data: HashMap<String, String>,
+    created: SystemTime,
+    regenerate: bool,
+    destroyed: bool,
+}
+
+/// The current request's session, shared with the `Sessions` middleware
+/// that loaded it. Take it as a handler argument, or with
+/// `Request::get_extension` in later middleware.
+///
+/// Changes are saved once the handler returns. A new session is only
+/// stored, and its cookie only sent, once something is set in it.
+#[derive(Debug, Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+    is_new: bool,
+}
+
+impl Session {
+    fn new(data: HashMap<String, String>, created: SystemTime, is_new: bool) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SessionState {
+                data,
+                created,
+                regenerate: false,
+                destroyed: false,
+            })),
+            is_new,
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, SessionState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state().data.get(key).cloned()
+    }
+
+    pub fn set(&self, key: &str, value: &str) {
+        self.state().data.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        self.state().data.remove(key)
+    }
+
+    pub fn clear(&self) {
+        self.state().data.clear();
+    }
+
+    /// Whether the client had no valid session before this request.
+    pub fn is_new(&self) -> bool {
+        self.is_new
+    }
+
+    /// Move the session to a fresh ID, keeping its data. Call it whenever
+    /// the user's privileges change, such as on login, so an ID planted or
+    /// seen before the change is worthless afterwards.
+    pub fn regenerate(&self) {
+        self.state().regenerate = true;
+    }
+
+    /// Delete the session from the store and the client, as on logout.
+    pub fn destroy(&self) {
+        let mut state = self.state();
+        state.data.clear();
+        state.destroyed = true;
+    }
+}
+
+/// The session attached by the `Sessions` middleware.
+impl FromRequest for Session {
+    fn from_request(req: &Request) -> Result<Self, Rejection> {
+        req.get_extension::<Session>().cloned().ok_or_else(|| {
+            Rejection::new(
+                StatusCode::InternalServerError,
+                "Sessions middleware is not installed",
+            )
+        })
+    }
+}
+
+/// Middleware that gives every request a `Session`, identified by a cookie
+/// holding a random ID and backed by a `SessionStore`.
+///
+/// ```ignore
+/// let sessions = Sessions::new(MemoryStore::new());
+/// server.add_middleware(move |req, next| sessions.handle(req, next));
+/// ```
+///
+/// Sessions expire after `idle_timeout` without a request (30 minutes by
+/// default) and `absolute_timeout` after they were created (12 hours), after
+/// which the client starts over with an empty one. Expired sessions are
+/// purged from the store at most once per idle timeout.
+pub struct Sessions {
+    store: Box<dyn SessionStore>,
+    cookie_name: String,
+    idle_timeout: Duration,
+    absolute_timeout: Duration,
+    secure: bool,
+    last_purge: Mutex<SystemTime>,
+}
+
+impl fmt::Debug for Sessions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sessions")
+            .field("cookie_name", &self.cookie_name)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("absolute_timeout", &self.absolute_timeout)
+            .field("secure", &self.secure)
+            .finish()
+    }
+}
+
+impl Sessions {
+    pub fn new<S: SessionStore + 'static>(store: S) -> Self {
+        Self {
+            store: Box::new(store),
+            cookie_name: "session".to_string(),
+            idle_timeout: Duration::from_secs(30 * 60),
+            absolute_timeout: Duration::from_secs(12 * 60 * 60),
+            secure: false,
+            last_purge: Mutex::new(SystemTime::now()),
+        }
+    }
+
+    pub fn set_cookie_name(&mut self, name: &str) {
+        self.cookie_name = name.to_string();
+    }
+
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = timeout;
+    }
+
+    pub fn set_absolute_timeout(&mut self, timeout: Duration) {
+        self.absolute_timeout = timeout;
+    }
+
+    /// Only send the session cookie over HTTPS.
+    pub fn set_secure(&mut self, secure: bool) {
+        self.secure = secure;
+    }
+
+    /// Load the session for `req`, run the rest of the chain, then save
+    /// whatever the handler changed.
+    pub fn handle(&self, req: &mut Request, next: Next) -> Response {
+        let now = SystemTime::now();
+        if let Err(err) = self.purge_expired(now) {
+            return err.into_response();
+        }
+
+        let sent_id = req
+            .get_cookies()
+            .get(&self.cookie_name)
+            .map(|id| id.to_string());
+        let mut loaded = None;
+        if let Some(id) = sent_id.as_deref().filter(|id| is_valid_id(id)) {
+            match self.store.load(id) {
+                Ok(Some(record))
+                    if record.is_expired(now, self.idle_timeout, self.absolute_timeout) =>
+                {
+                    if let Err(err) = self.store.remove(id) {
+                        return err.into_response();
+                    }
+                }
+                Ok(Some(record)) => loaded = Some((id.to_string(), record)),
+                Ok(None) => {}
+                Err(err) => return err.into_response(),
+            }
+        }
+
+        let session = match &loaded {
+            Some((_, record)) => Session::new(record.data.clone(), record.created, false),
+            None => Session::new(HashMap::new(), now, true),
+        };
+        req.insert_extension(session.clone());
+        let mut res = next(req);
+
+        let state = session.state();
+        let old_id = loaded.map(|(id, _)| id);
+        if state.destroyed || (old_id.is_none() && state.data.is_empty()) {
+            if let Some(id) = &old_id {
+                if let Err(err) = self.store.remove(id) {
+                    return err.into_response();
+                }
+            }
+            // a stale or destroyed ID is of no further use to the client
+            if sent_id.is_some() {
+                res.add_cookie(self.cookie(None));
+            }
+            return res;
+        }
+
+        let id = match old_id {
+            Some(id) if !state.regenerate => id,
+            old_id => {
+                if let Some(old_id) = old_id {
+                    if let Err(err) = self.store.remove(&old_id) {
+                        return err.into_response();
+                    }
+                }
+                match generate_id() {
+                    Ok(id) => {
+                        res.add_cookie(self.cookie(Some(&id)));
+                        id
+                    }
+                    Err(err) => return err.into_response(),
+                }
+            }
+        };
+        // saved even when unchanged, to record the access
+        let record = SessionRecord {
+            data: state.data.clone(),
+            created: state.created,
+            last_access: now,
+        };
+        match self.store.save(&id, &record) {
+            Ok(()) => res,
+            Err(err) => err.into_response(),
+        }
+    }
+
+    /// The session cookie carrying `id`, or one removing it.
+    fn cookie(&self, id: Option<&str>) -> Cookie {
+        let mut cookie = match id {
+            Some(id) => Cookie::new(&self.cookie_name, id),
+            None => Cookie::removal(&self.cookie_name),
+        };
+        cookie.set_path("/");
+        cookie.set_http_only(true);
+        cookie.set_same_site(SameSite::Lax);
+        cookie.set_secure(self.secure);
+        cookie
+    }
+
+    fn purge_expired(&self, now: SystemTime) -> io::Result<()> {
+        let mut last_purge = self
+            .last_purge
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if now.duration_since(*last_purge).unwrap_or_default() < self.idle_timeout {
+            return Ok(());
+        }
+        *last_purge = now;
+        self.store.remove_expired(&|record| {
+            record.is_expired(now, self.idle_timeout, self.absolute_timeout)
+        })
+    }
+}
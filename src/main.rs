@@ -1,92 +1,86 @@
-mod http;
-use std::{collections::HashMap, env, fs, path, sync::Arc};
+use std::{env, path};
 
-use http::Server;
+use http_server_starter_rust::http::{
+    self, BodyReader, ContentType, FileError, IntoResponse, Multipart, MultipartError, Path,
+    Request, Response, Server, State, StaticFiles, StatusCode, UserAgent,
+};
 
-use crate::http::{HTTPVersion, Request, RequestBody, Response, StatusCode};
+/// Directory served by the `/files` routes, taken from the last CLI argument.
+#[derive(Clone)]
+struct FilesDir(String);
 
 fn main() -> Result<(), std::io::Error> {
     // You can use print statements as follows for debugging, they'll be visible when running tests.
 
     // Uncomment this block to pass the first stage
     let mut server = Server::new("127.0.0.1:4221")?;
-    server.add_route(http::Method::GET, "/", |req: &Request| {
-        Response::new(HTTPVersion::HTTP1_1, HashMap::new(), StatusCode::Ok)
-    });
+    let dir = env::args().next_back().unwrap_or("/tmp/".to_string());
+    server.add_state(FilesDir(dir.clone()));
 
-    server.add_route(http::Method::GET, "/echo/{cont}", |req: &Request| {
-        let mut headers: HashMap<String, String> = HashMap::new();
-        let params = req.get_params();
-        let d = String::new();
-        let content = params.get("cont").unwrap_or(&d);
-        headers.insert(String::from("Content-Type"), String::from("text/plain"));
-        headers.insert(String::from("Content-Length"), content.len().to_string());
-        let mut res = Response::new(HTTPVersion::HTTP1_1, headers, StatusCode::Ok);
-        res.set_body(RequestBody::String(content.as_bytes().to_vec()));
+    server.add_route(http::Method::GET, "/", |_req: &Request| StatusCode::Ok);
 
-        res
-    });
+    server.add_route(
+        http::Method::GET,
+        "/echo/{cont}",
+        |Path(cont): Path<String>| Response::text(cont),
+    );
 
-    server.add_route(http::Method::GET, "/user-agent", |req: &Request| {
-        let mut headers: HashMap<String, String> = HashMap::new();
-        let content = req.get_headers().get("user-agent").unwrap();
-        headers.insert(String::from("Content-Type"), String::from("text/plain"));
-        headers.insert(String::from("Content-Length"), content.len().to_string());
-        let mut res = Response::new(HTTPVersion::HTTP1_1, headers, StatusCode::Ok);
-        res.set_body(RequestBody::String(content.as_bytes().to_vec()));
+    server.add_route(
+        http::Method::GET,
+        "/user-agent",
+        |UserAgent(ua): UserAgent| Response::text(ua),
+    );
 
-        res
-    });
+    let mut files = StaticFiles::new(&dir);
+    files.set_writable(true);
+    files.set_create_dirs(true);
+    server.mount("/files", files);
 
-    server.add_route(http::Method::GET, "/files/{file_name}", |req: &Request| {
-        let dir = env::args().last().unwrap_or("/tmp/".to_string());
-
-        let params = req.get_params();
-        let d = String::new();
-        let file_name = params.get("file_name").unwrap_or(&d);
-        let p = path::PathBuf::from(format!("{}{}", &dir, &file_name));
-
-        let mut headers: HashMap<String, String> = HashMap::new();
-        if p.exists() && p.is_file() {
-            let content = fs::read_to_string(p).unwrap_or("".to_string());
-            headers.insert(
-                String::from("Content-Type"),
-                String::from("application/octet-stream"),
-            );
-            headers.insert(String::from("Content-Length"), content.len().to_string());
-            let mut res = Response::new(HTTPVersion::HTTP1_1, headers, StatusCode::Ok);
-            res.set_body(RequestBody::String(content.as_bytes().to_vec()));
-            res
-        } else {
-            Response::new(HTTPVersion::HTTP1_1, HashMap::new(), StatusCode::NotFound)
-        }
-    });
-
-    server.add_route(http::Method::POST, "/files/{file_name}", |req: &Request| {
-        let dir = env::args().last().unwrap_or("/tmp/".to_string());
-
-        let params = req.get_params();
-        let d = String::new();
-        let file_name = params.get("file_name").unwrap_or(&d);
-        let p = path::PathBuf::from(format!("{}{}", &dir, &file_name));
-        let body = req.get_body();
-        if let Some(body) = body {
-            match body {
-                RequestBody::String(bytes) => match fs::write(p, bytes) {
-                    Ok(_) => {
-                        Response::new(HTTPVersion::HTTP1_1, HashMap::new(), StatusCode::Created)
-                    }
-                    Err(_) => {
-                        Response::new(HTTPVersion::HTTP1_1, HashMap::new(), StatusCode::NotFound)
-                    }
-                },
+    server.add_streaming_route(
+        http::Method::POST,
+        "/files/{file_name}",
+        |State(FilesDir(dir)): State<FilesDir>,
+         Path(file_name): Path<String>,
+         content_type: Option<ContentType>,
+         body: BodyReader| {
+            let p = match http::resolve_path(path::Path::new(&dir), &file_name) {
+                Ok(p) => p,
+                Err(err) => return err.into_response(),
+            };
+            let written = match content_type {
+                // an HTML form upload: store the file it carries, not the envelope
+                Some(ContentType(ct)) if ct.to_lowercase().starts_with("multipart/form-data") => {
+                    Multipart::with_content_type(&ct, body)
+                        .map_err(IntoResponse::into_response)
+                        .and_then(|multipart| save_first_file(&p, multipart))
+                }
+                _ => http::write_file_from(&p, body, false)
+                    .map(|_| ())
+                    .map_err(IntoResponse::into_response),
+            };
+            match written {
+                Ok(()) => StatusCode::Created.into_response(),
+                Err(res) => res,
             }
-        } else {
-            Response::new(HTTPVersion::HTTP1_1, HashMap::new(), StatusCode::NotFound)
-        }
-    });
+        },
+    );
 
     server.run();
 
     Ok(())
 }
+
+/// Write the first file part of `multipart` to `path`.
+fn save_first_file(path: &path::Path, mut multipart: Multipart) -> Result<(), Response> {
+    while let Some(part) = multipart.next_part().map_err(IntoResponse::into_response)? {
+        if part.is_file() {
+            http::write_file_from(path, part, false).map_err(|err| match err {
+                // limits hit while reading the part surface as io errors
+                FileError::Io(err) => MultipartError::from(err).into_response(),
+                err => err.into_response(),
+            })?;
+            return Ok(());
+        }
+    }
+    Err((StatusCode::BadRequest, "No file in form").into_response())
+}